@@ -0,0 +1,345 @@
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use rand::{seq::SliceRandom, thread_rng, Rng};
+
+use crate::{
+    garbage::{attack_lines, exchange_garbage},
+    lobby::Lobby,
+    modes::{goal_reached, piece_buffer, prepare_board, ULTRA_TIME},
+    movement::{apply_move, enter_board, play_move},
+    network::{ClientMessage, ClientResource, LoopbackTransport, Message},
+    pieces::PieceSet,
+    replay::ReplayEvent,
+    tetris::*,
+    GameMode, GameSettings, GameState, TetrisMove,
+};
+
+pub struct BotPlugin;
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<BotMoveEvent>();
+
+        app.add_enter_system(GameState::Playing, reset_bot);
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_if_resource_exists::<BotPlayer>()
+                .with_system(bot_receive)
+                .with_system(bot_ready.run_in_state(GameState::Lobby))
+                .into(),
+        );
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .run_if_resource_exists::<BotPlayer>()
+                .with_system(bot_think)
+                .with_system(bot_move_piece)
+                .into(),
+        );
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct BotMoveEvent(pub TetrisMove);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BotDifficulty {
+    Easy,
+    #[default]
+    Medium,
+    Hard,
+}
+
+impl BotDifficulty {
+    pub fn next(self) -> Self {
+        match self {
+            BotDifficulty::Easy => BotDifficulty::Medium,
+            BotDifficulty::Medium => BotDifficulty::Hard,
+            BotDifficulty::Hard => BotDifficulty::Easy,
+        }
+    }
+    /// Chance of picking one of the next best placements instead of the best
+    fn mistake_chance(self) -> f64 {
+        match self {
+            BotDifficulty::Easy => 0.4,
+            BotDifficulty::Medium => 0.1,
+            BotDifficulty::Hard => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BotSpeed {
+    Slow,
+    #[default]
+    Normal,
+    Fast,
+}
+
+impl BotSpeed {
+    pub fn next(self) -> Self {
+        match self {
+            BotSpeed::Slow => BotSpeed::Normal,
+            BotSpeed::Normal => BotSpeed::Fast,
+            BotSpeed::Fast => BotSpeed::Slow,
+        }
+    }
+    /// Time between each move
+    fn move_time(self) -> Duration {
+        match self {
+            BotSpeed::Slow => Duration::from_millis(400),
+            BotSpeed::Normal => Duration::from_millis(150),
+            BotSpeed::Fast => Duration::from_millis(50),
+        }
+    }
+}
+
+/// Plays in place of the other player, connected to us through a loopback
+/// transport so it looks just like a network peer
+#[derive(Resource)]
+pub struct BotPlayer {
+    pub difficulty: BotDifficulty,
+    pub speed: BotSpeed,
+    connection: ClientResource,
+    board: TetrisBoard,
+    piece: Option<CurrentPiece>,
+    buffer: TetrisPieceBuffer,
+    plan: VecDeque<TetrisMove>,
+    move_timer: Timer,
+    stats: GameStats,
+    settings: GameSettings,
+    chain: ClearChain,
+    pending_garbage: usize,
+}
+
+impl BotPlayer {
+    /// Returns the bot and our end of the connection to it
+    pub fn new() -> (Self, ClientResource) {
+        let (ours, theirs) = LoopbackTransport::pair();
+        let bot = Self {
+            difficulty: BotDifficulty::default(),
+            speed: BotSpeed::default(),
+            connection: ClientResource::new(theirs),
+            board: TetrisBoard::new(Vec2::ZERO, STANDARD_BOARD_SIZE),
+            piece: None,
+            buffer: TetrisPieceBuffer::new(0, PieceSet::standard()),
+            plan: VecDeque::new(),
+            move_timer: Timer::default(),
+            stats: GameStats::default(),
+            settings: GameSettings::default(),
+            chain: ClearChain::default(),
+            pending_garbage: 0,
+        };
+        (bot, ClientResource::new(ours))
+    }
+}
+
+fn reset_bot(mut bot: ResMut<BotPlayer>, settings: Res<GameSettings>) {
+    let bot = &mut *bot;
+    bot.board = TetrisBoard::for_settings(Vec2::ZERO, &settings);
+    prepare_board(&settings, &mut bot.board);
+    bot.buffer = piece_buffer(&settings);
+    let mut piece = bot.buffer.next_piece(bot.board.tiles.len());
+    enter_board(&mut piece, &bot.board);
+    bot.piece = Some(piece);
+    bot.plan = plan_moves(&bot.board, bot.piece.as_ref().unwrap(), bot.difficulty);
+    bot.move_timer = Timer::new(bot.speed.move_time(), TimerMode::Repeating);
+    bot.stats = GameStats::default();
+    bot.settings = settings.clone();
+    bot.chain = ClearChain::default();
+    bot.pending_garbage = 0;
+}
+
+fn bot_ready(mut lobby: ResMut<Lobby>) {
+    if !lobby.client_ready {
+        lobby.client_ready = true;
+    }
+}
+
+/// The bot only cares about the garbage we send it
+fn bot_receive(mut bot: ResMut<BotPlayer>) {
+    for message in bot.connection.receive::<Message>() {
+        if let Message::Client(ClientMessage::Garbage(rows)) = message {
+            bot.pending_garbage += rows;
+        }
+    }
+}
+
+fn bot_think(
+    time: Res<Time>,
+    mut bot: ResMut<BotPlayer>,
+    mut move_events: EventWriter<BotMoveEvent>,
+) {
+    let last_time = bot.stats.time;
+    bot.stats.time += time.delta();
+    // The host waits for the final score once the bot's clock runs out
+    if bot.settings.mode == GameMode::Ultra
+        && last_time < ULTRA_TIME
+        && bot.stats.time >= ULTRA_TIME
+    {
+        let stats = bot.stats.clone();
+        bot.connection.send(ClientMessage::Stats(stats.clone()));
+        bot.connection.send(ClientMessage::TimeUp(stats.score));
+        bot.piece = None;
+    }
+    if !bot.move_timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    if let Some(m) = bot.plan.pop_front() {
+        move_events.send(BotMoveEvent(m));
+    }
+}
+
+pub fn bot_gravity(mut move_events: EventWriter<BotMoveEvent>) {
+    move_events.send(BotMoveEvent(TetrisMove::Fall));
+}
+
+fn bot_move_piece(mut move_events: EventReader<BotMoveEvent>, mut bot: ResMut<BotPlayer>) {
+    let bot = &mut *bot;
+    let piece = match bot.piece.as_mut() {
+        Some(piece) => piece,
+        None => {
+            move_events.clear();
+            return;
+        }
+    };
+
+    let mut replay_events = vec![];
+    let mut stop_falling = false;
+    for BotMoveEvent(m) in move_events.iter() {
+        replay_events.push((bot.stats.time, ReplayEvent::Move(*m)));
+        let (dropped, lands) = play_move(piece, &bot.board, *m);
+        bot.stats.score += drop_points(*m, dropped);
+        if lands {
+            stop_falling = true;
+            break;
+        }
+    }
+    move_events.clear();
+
+    if replay_events.is_empty() {
+        return;
+    }
+
+    if stop_falling {
+        let t_spin = t_spin(piece, &bot.board);
+        bot.board.place(piece);
+        let cleared = clear_board(&mut bot.board, &mut bot.chain, t_spin);
+        bot.stats.pieces += 1;
+        bot.stats.add_clear(&cleared);
+        let mut buried = false;
+        if bot.settings.mode.sends_garbage() {
+            let attack = attack_lines(&cleared);
+            let (sent, rows) = exchange_garbage(attack, cleared.lines, &mut bot.pending_garbage);
+            if sent > 0 {
+                bot.connection.send(ClientMessage::Garbage(sent));
+            }
+            if rows > 0 {
+                let hole = thread_rng().gen_range(0..bot.board.tiles.len());
+                replay_events.push((bot.stats.time, ReplayEvent::Garbage { rows, hole }));
+                buried = !bot.board.add_garbage(rows, hole);
+            }
+        }
+        bot.connection
+            .send(ClientMessage::BoardUpdate(bot.board.tiles.clone()));
+        bot.connection.send(ClientMessage::Stats(bot.stats.clone()));
+
+        let mut next = bot.buffer.next_piece(bot.board.tiles.len());
+        if goal_reached(&bot.settings, &bot.stats, &bot.board) {
+            bot.connection.send(ClientMessage::GoalReached);
+            bot.piece = None;
+        } else if buried || !enter_board(&mut next, &bot.board) {
+            bot.connection.send(ClientMessage::ToppedOut);
+            bot.piece = None;
+        } else {
+            bot.plan = plan_moves(&bot.board, &next, bot.difficulty);
+            bot.piece = Some(next);
+        }
+    }
+
+    let tiles = bot.piece.as_ref().map_or(vec![], |e| e.tiles.clone());
+    bot.connection.send(ClientMessage::PieceUpdate(tiles));
+    bot.connection
+        .send(ClientMessage::ReplayEvents(replay_events));
+}
+
+/// Tries every rotation and column for the piece and returns the moves to get
+/// to the best one, finishing with a hard drop
+fn plan_moves(
+    board: &TetrisBoard,
+    piece: &CurrentPiece,
+    difficulty: BotDifficulty,
+) -> VecDeque<TetrisMove> {
+    let mut placements = vec![];
+    // Far enough to reach either wall from the middle
+    let reach = board.tiles.len() as i32 / 2 + 1;
+    for rotations in 0..4 {
+        for shift in -reach..=reach {
+            let mut moves = vec![TetrisMove::RotateRight; rotations];
+            let side = if shift < 0 {
+                TetrisMove::Left
+            } else {
+                TetrisMove::Right
+            };
+            moves.extend(std::iter::repeat(side).take(shift.unsigned_abs() as usize));
+
+            let mut piece = piece.clone();
+            if !moves.iter().all(|m| apply_move(&mut piece, board, *m)) {
+                continue;
+            }
+            while apply_move(&mut piece, board, TetrisMove::Fall) {}
+
+            let mut board = board.clone();
+            board.place(&piece);
+            let lines = board.clear_lines();
+            placements.push((evaluate(&board, lines), moves));
+        }
+    }
+
+    placements.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut rng = thread_rng();
+    let choice = if rng.gen_bool(difficulty.mistake_chance()) {
+        placements
+            .iter()
+            .take(5)
+            .collect::<Vec<_>>()
+            .choose(&mut rng)
+            .copied()
+    } else {
+        placements.first()
+    };
+
+    let mut plan: VecDeque<_> = choice.map(|(_, e)| e.clone()).unwrap_or_default().into();
+    plan.push_back(TetrisMove::HardDrop);
+    plan
+}
+
+/// Weights from https://codemyroad.wordpress.com/2013/04/14/tetris-ai-the-near-perfect-player/
+fn evaluate(board: &TetrisBoard, lines: usize) -> f32 {
+    let heights: Vec<i32> = board
+        .tiles
+        .iter()
+        .map(|col| {
+            col.iter()
+                .position(|e| e.is_some())
+                .map_or(0, |top| (col.len() - top) as i32)
+        })
+        .collect();
+    let holes: usize = board
+        .tiles
+        .iter()
+        .map(|col| match col.iter().position(|e| e.is_some()) {
+            Some(top) => col[top..].iter().filter(|e| e.is_none()).count(),
+            None => 0,
+        })
+        .sum();
+    let aggregate_height: i32 = heights.iter().sum();
+    let bumpiness: i32 = heights.windows(2).map(|e| (e[0] - e[1]).abs()).sum();
+
+    -0.51 * aggregate_height as f32 + 0.76 * lines as f32
+        - 0.36 * holes as f32
+        - 0.18 * bumpiness as f32
+}
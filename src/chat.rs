@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::network::{ClientMessage, ClientResource};
+
+const HISTORY_LENGTH: usize = 8;
+const MAX_MESSAGE_LENGTH: usize = 100;
+
+pub struct ChatPlugin;
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ChatInput::default());
+        app.insert_resource(ChatHistory::default());
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_if_resource_exists::<ClientResource>()
+                .with_system(chat_input)
+                .with_system(receive_chat)
+                .into(),
+        );
+    }
+}
+
+/// The message being typed, while open all keyboard input goes to the chat
+#[derive(Resource, Default)]
+pub struct ChatInput {
+    pub open: bool,
+    pub text: String,
+}
+
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct ChatHistory(pub VecDeque<String>);
+
+impl ChatHistory {
+    fn push(&mut self, line: String) {
+        self.push_back(line);
+        if self.len() > HISTORY_LENGTH {
+            self.pop_front();
+        }
+    }
+}
+
+/// Run condition for systems reading the keyboard
+pub fn chat_closed(chat: Res<ChatInput>) -> bool {
+    !chat.open
+}
+
+/// Sent as a character when backspace is pressed
+pub const BACKSPACE_CHAR: char = 8 as char;
+
+fn chat_input(
+    keys: Res<Input<KeyCode>>,
+    mut char_events: EventReader<ReceivedCharacter>,
+    mut input: ResMut<ChatInput>,
+    mut history: ResMut<ChatHistory>,
+    mut client: ResMut<ClientResource>,
+) {
+    if !input.open {
+        char_events.clear();
+        if keys.just_pressed(KeyCode::Return) {
+            input.open = true;
+        }
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::Escape) {
+        char_events.clear();
+        input.open = false;
+        input.text.clear();
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::Return) {
+        char_events.clear();
+        input.open = false;
+        let text = std::mem::take(&mut input.text);
+        if !text.trim().is_empty() {
+            history.push(format!("You: {text}"));
+            client.send(ClientMessage::Chat(text));
+        }
+        return;
+    }
+
+    for e in char_events.iter() {
+        match e.char {
+            BACKSPACE_CHAR => {
+                input.text.pop();
+            }
+            c if c.is_control() => {}
+            c => {
+                if input.text.chars().count() < MAX_MESSAGE_LENGTH {
+                    input.text.push(c);
+                }
+            }
+        }
+    }
+}
+
+fn receive_chat(mut messages: EventReader<ClientMessage>, mut history: ResMut<ChatHistory>) {
+    for message in messages.iter() {
+        if let ClientMessage::Chat(text) = message {
+            // Held to the same limits as our own messages
+            let text: String = text
+                .chars()
+                .filter(|c| !c.is_control())
+                .take(MAX_MESSAGE_LENGTH)
+                .collect();
+            history.push(format!("Opponent: {text}"));
+        }
+    }
+}
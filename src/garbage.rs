@@ -0,0 +1,115 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use rand::{thread_rng, Rng};
+
+use crate::{
+    network::{ClientMessage, ClientResource},
+    replay::{ReplayEvent, ReplayRecorder},
+    tetris::*,
+    GameSettings, GameState,
+};
+
+pub struct GarbagePlugin;
+impl Plugin for GarbagePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(GameState::Playing, reset_garbage);
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .run_if(|settings: Res<GameSettings>| settings.mode.sends_garbage())
+                .run_if_resource_exists::<ClientResource>()
+                .run_if_resource_exists::<PendingGarbage>()
+                .with_system(receive_garbage)
+                .with_system(send_garbage)
+                .into(),
+        );
+    }
+}
+
+/// Lines the other player sent us that haven't risen up yet
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct PendingGarbage(pub usize);
+
+/// Extra lines for each clear in a row, past the end it stays at the last
+const COMBO_GARBAGE: [usize; 11] = [0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+
+/// Added on top of everything else for leaving the board empty
+const PERFECT_CLEAR_GARBAGE: usize = 10;
+
+/// Lines of garbage a clear sends to the other player
+pub fn attack_lines(e: &LinesClearedEvent) -> usize {
+    let lines = match e.t_spin {
+        TSpin::Full => e.lines * 2,
+        TSpin::Mini => e.lines.saturating_sub(1),
+        TSpin::None => match e.lines {
+            2 => 1,
+            3 => 2,
+            4 => 4,
+            _ => 0,
+        },
+    };
+    let combo = COMBO_GARBAGE[e.combo.min(COMBO_GARBAGE.len() - 1)];
+    let perfect_clear = match e.perfect_clear {
+        true => PERFECT_CLEAR_GARBAGE,
+        false => 0,
+    };
+    lines + combo + e.back_to_back as usize + perfect_clear
+}
+
+/// Clearing lines cancels incoming garbage first and sends the rest, placing
+/// a piece without clearing anything lets the incoming garbage rise
+pub fn exchange_garbage(attack: usize, lines: usize, pending: &mut usize) -> (usize, usize) {
+    let cancelled = attack.min(*pending);
+    *pending -= cancelled;
+    let rising = match lines {
+        0 => std::mem::take(pending),
+        _ => 0,
+    };
+    (attack - cancelled, rising)
+}
+
+fn reset_garbage(mut commands: Commands) {
+    commands.insert_resource(PendingGarbage::default());
+}
+
+fn receive_garbage(mut messages: EventReader<ClientMessage>, mut pending: ResMut<PendingGarbage>) {
+    for message in messages.iter() {
+        if let ClientMessage::Garbage(rows) = message {
+            **pending += rows;
+        }
+    }
+}
+
+fn send_garbage(
+    time: Res<Time>,
+    mut cleared: EventReader<LinesClearedEvent>,
+    mut pending: ResMut<PendingGarbage>,
+    mut board: ResMut<OwnTetrisBoard>,
+    piece: Option<Res<CurrentPiece>>,
+    mut client: ResMut<ClientResource>,
+    mut recorder: ResMut<ReplayRecorder>,
+    mut topped_out: EventWriter<ToppedOutEvent>,
+) {
+    for e in cleared.iter() {
+        let attack = attack_lines(e);
+        let (sent, rows) = exchange_garbage(attack, e.lines, &mut pending);
+        if sent > 0 {
+            client.send(ClientMessage::Garbage(sent));
+        }
+        if rows == 0 {
+            continue;
+        }
+
+        let hole = thread_rng().gen_range(0..board.tiles.len());
+        recorder.record_event(&time, ReplayEvent::Garbage { rows, hole });
+        // The next piece may already be out and in the way
+        let fits = board.add_garbage(rows, hole);
+        let blocked = piece.as_ref().map_or(false, |piece| {
+            piece.tiles.iter().any(|(pos, _)| !board.tile_empty(*pos))
+        });
+        if !fits || blocked {
+            topped_out.send(ToppedOutEvent);
+        }
+    }
+}
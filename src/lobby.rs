@@ -0,0 +1,338 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    modes::{Puzzle, CHEESE_ROWS_OPTIONS, MARATHON_LEVELS},
+    network::{ClientMessage, ClientResource, HostMessage, Latency, NetworkState},
+    pieces::PieceSet,
+    series::Series,
+    tetris::STANDARD_BOARD_SIZE,
+    GameMode, GameSettings, GameState,
+};
+
+pub const COUNTDOWN: Duration = Duration::from_secs(3);
+pub const GRAVITY_OPTIONS: [Duration; 4] = [
+    Duration::from_millis(1000),
+    Duration::from_millis(750),
+    Duration::from_millis(500),
+    Duration::from_millis(250),
+];
+pub const BEST_OF_OPTIONS: [u32; 4] = [1, 3, 5, 7];
+/// Columns and visible rows, from the usual board to narrow wells and big
+/// boards
+pub const BOARD_SIZE_OPTIONS: [(usize, usize); 5] =
+    [STANDARD_BOARD_SIZE, (4, 20), (16, 20), (10, 30), (16, 30)];
+
+pub struct LobbyPlugin;
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Lobby::default());
+        app.add_event::<LobbyAction>();
+
+        app.add_exit_system(GameState::Lobby, cleanup_lobby);
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Lobby)
+                .with_system(handle_lobby_actions)
+                .with_system(receive_lobby_messages)
+                .with_system(tick_countdown.run_if_resource_exists::<Countdown>())
+                .into(),
+        );
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Lobby)
+                .run_not_in_state(NetworkState::Client)
+                .run_if_resource_exists::<ClientResource>()
+                .with_system(send_lobby_updates)
+                .with_system(start_countdown.run_unless_resource_exists::<Countdown>())
+                .into(),
+        );
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Lobby)
+                .run_in_state(NetworkState::None)
+                .run_unless_resource_exists::<ClientResource>()
+                .run_unless_resource_exists::<Countdown>()
+                .with_system(start_solo_countdown)
+                .into(),
+        );
+    }
+}
+
+/// The host owns the lobby and sends it to the client whenever it changes
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Lobby {
+    pub connected: bool,
+    pub host_ready: bool,
+    pub client_ready: bool,
+    pub series: Series,
+}
+
+/// Counts down to the start of the match
+#[derive(Resource, Deref, DerefMut)]
+pub struct Countdown(pub Timer);
+
+pub enum LobbyAction {
+    ToggleReady,
+    CycleMode,
+    CycleGravity,
+    CycleBestOf,
+    CycleStartLevel,
+    ToggleEndless,
+    CycleCheeseRows,
+    CyclePuzzle,
+    CycleBoardSize,
+    CyclePieceSet,
+}
+
+fn handle_lobby_actions(
+    mut actions: EventReader<LobbyAction>,
+    network_state: Res<CurrentState<NetworkState>>,
+    mut lobby: ResMut<Lobby>,
+    mut settings: ResMut<GameSettings>,
+    mut client: Option<ResMut<ClientResource>>,
+    countdown: Option<Res<Countdown>>,
+) {
+    // Nothing can change once the countdown has started
+    if countdown.is_some() {
+        actions.clear();
+        return;
+    }
+
+    // Playing against the bot we are the host
+    let is_host = network_state.0 != NetworkState::Client;
+    for action in actions.iter() {
+        match action {
+            LobbyAction::ToggleReady if is_host => lobby.host_ready = !lobby.host_ready,
+            LobbyAction::ToggleReady => {
+                if let Some(client) = client.as_mut() {
+                    client.send(ClientMessage::Ready(!lobby.client_ready));
+                }
+            }
+            LobbyAction::CycleMode if is_host => {
+                let solo = network_state.0 == NetworkState::None && client.is_none();
+                let i = GameMode::ALL.iter().position(|m| *m == settings.mode);
+                let next = (1..=GameMode::ALL.len())
+                    .map(|step| GameMode::ALL[(i.unwrap_or(0) + step) % GameMode::ALL.len()])
+                    .find(|mode| match mode {
+                        // Co-op needs someone on the other end to play with
+                        GameMode::Coop => network_state.0 != NetworkState::None,
+                        // Zen is only for practicing alone
+                        GameMode::Zen => solo,
+                        // There is nothing to play without any puzzle files
+                        GameMode::Puzzle => {
+                            if settings.puzzle.is_none() {
+                                settings.puzzle = next_puzzle(None);
+                            }
+                            settings.puzzle.is_some()
+                        }
+                        _ => true,
+                    });
+                settings.mode = next.unwrap_or_default();
+                lobby.host_ready = false;
+                lobby.client_ready = false;
+            }
+            LobbyAction::CycleGravity if is_host => {
+                let i = GRAVITY_OPTIONS.iter().position(|g| *g == settings.gravity);
+                settings.gravity = GRAVITY_OPTIONS[i.map_or(0, |i| i + 1) % GRAVITY_OPTIONS.len()];
+                lobby.host_ready = false;
+                lobby.client_ready = false;
+            }
+            LobbyAction::CycleBestOf if is_host => {
+                let i = BEST_OF_OPTIONS.iter().position(|b| *b == settings.best_of);
+                settings.best_of = BEST_OF_OPTIONS[i.map_or(0, |i| i + 1) % BEST_OF_OPTIONS.len()];
+                lobby.host_ready = false;
+                lobby.client_ready = false;
+                lobby.series = Series::default();
+            }
+            LobbyAction::CycleStartLevel if is_host => {
+                settings.start_level = settings.start_level % MARATHON_LEVELS + 1;
+                lobby.host_ready = false;
+                lobby.client_ready = false;
+            }
+            LobbyAction::ToggleEndless if is_host => {
+                settings.endless = !settings.endless;
+                lobby.host_ready = false;
+                lobby.client_ready = false;
+            }
+            LobbyAction::CycleCheeseRows if is_host => {
+                let i = CHEESE_ROWS_OPTIONS
+                    .iter()
+                    .position(|r| *r == settings.cheese_rows);
+                settings.cheese_rows =
+                    CHEESE_ROWS_OPTIONS[i.map_or(0, |i| i + 1) % CHEESE_ROWS_OPTIONS.len()];
+                lobby.host_ready = false;
+                lobby.client_ready = false;
+            }
+            LobbyAction::CycleBoardSize if is_host => {
+                settings.board_size = next_board_size(settings.board_size, &settings.piece_set);
+                lobby.host_ready = false;
+                lobby.client_ready = false;
+            }
+            LobbyAction::CyclePieceSet if is_host => {
+                settings.piece_set = next_piece_set(&settings.piece_set);
+                // Bigger pieces might not fit the board that was picked
+                if settings.board_size.0 < settings.piece_set.width() {
+                    settings.board_size = next_board_size(settings.board_size, &settings.piece_set);
+                }
+                lobby.host_ready = false;
+                lobby.client_ready = false;
+            }
+            LobbyAction::CyclePuzzle if is_host => {
+                if let Some(puzzle) = next_puzzle(settings.puzzle.as_ref()) {
+                    settings.puzzle = Some(puzzle);
+                }
+                lobby.host_ready = false;
+                lobby.client_ready = false;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Loads the puzzle after the current one, skipping any that fail to load
+fn next_puzzle(current: Option<&Puzzle>) -> Option<Puzzle> {
+    let names = Puzzle::list();
+    let start = current
+        .and_then(|e| names.iter().position(|name| *name == e.name))
+        .map_or(0, |i| i + 1);
+    (0..names.len())
+        .map(|i| &names[(start + i) % names.len()])
+        .find_map(|name| match Puzzle::load(name) {
+            Ok(puzzle) => Some(puzzle),
+            Err(e) => {
+                println!("{e}");
+                None
+            }
+        })
+}
+
+/// The board sizes in order, skipping any too narrow for the pieces
+fn next_board_size(current: (usize, usize), set: &PieceSet) -> (usize, usize) {
+    let start = BOARD_SIZE_OPTIONS
+        .iter()
+        .position(|e| *e == current)
+        .map_or(0, |i| i + 1);
+    (0..BOARD_SIZE_OPTIONS.len())
+        .map(|i| BOARD_SIZE_OPTIONS[(start + i) % BOARD_SIZE_OPTIONS.len()])
+        .find(|(width, _)| *width >= set.width())
+        .unwrap_or(STANDARD_BOARD_SIZE)
+}
+
+/// The piece set files in order and then back to the standard set, skipping
+/// any that fail to load or are too wide for every board size
+fn next_piece_set(current: &PieceSet) -> PieceSet {
+    let names = PieceSet::list();
+    let start = names
+        .iter()
+        .position(|name| *name == current.name)
+        .map_or(0, |i| i + 1);
+    names[start..]
+        .iter()
+        .find_map(|name| match PieceSet::load(name) {
+            Ok(set)
+                if BOARD_SIZE_OPTIONS
+                    .iter()
+                    .all(|(width, _)| *width < set.width()) =>
+            {
+                println!("Piece set {name} doesn't fit on any board size");
+                None
+            }
+            Ok(set) => Some(set),
+            Err(e) => {
+                println!("{e}");
+                None
+            }
+        })
+        .unwrap_or_default()
+}
+
+fn receive_lobby_messages(
+    mut commands: Commands,
+    mut host_messages: EventReader<HostMessage>,
+    mut client_messages: EventReader<ClientMessage>,
+    mut lobby: ResMut<Lobby>,
+    mut settings: ResMut<GameSettings>,
+) {
+    for message in host_messages.iter() {
+        match message {
+            HostMessage::Lobby(new_lobby, new_settings) => {
+                *lobby = new_lobby.clone();
+                *settings = new_settings.clone();
+            }
+            HostMessage::StartCountdown(e, seed) => {
+                settings.seed = *seed;
+                commands.insert_resource(Countdown(Timer::new(*e, TimerMode::Once)));
+            }
+            _ => {}
+        }
+    }
+    for message in client_messages.iter() {
+        if let ClientMessage::Ready(e) = message {
+            lobby.client_ready = *e;
+        }
+    }
+}
+
+fn send_lobby_updates(
+    mut client: ResMut<ClientResource>,
+    mut lobby: ResMut<Lobby>,
+    settings: Res<GameSettings>,
+) {
+    if client.is_added() {
+        lobby.connected = true;
+    }
+    if lobby.is_changed() || settings.is_changed() {
+        client.send(HostMessage::Lobby(lobby.clone(), settings.clone()));
+    }
+}
+
+fn start_countdown(
+    mut commands: Commands,
+    lobby: Res<Lobby>,
+    latency: Res<Latency>,
+    mut settings: ResMut<GameSettings>,
+    mut client: ResMut<ClientResource>,
+) {
+    if !lobby.host_ready || !lobby.client_ready {
+        return;
+    }
+    settings.seed = rand::random();
+    // The message takes about half the round trip to arrive
+    client.send(HostMessage::StartCountdown(
+        COUNTDOWN.saturating_sub(**latency / 2),
+        settings.seed,
+    ));
+    commands.insert_resource(Countdown(Timer::new(COUNTDOWN, TimerMode::Once)));
+}
+
+/// Playing alone we only wait for ourselves
+fn start_solo_countdown(
+    mut commands: Commands,
+    lobby: Res<Lobby>,
+    mut settings: ResMut<GameSettings>,
+) {
+    if !lobby.host_ready {
+        return;
+    }
+    settings.seed = rand::random();
+    commands.insert_resource(Countdown(Timer::new(COUNTDOWN, TimerMode::Once)));
+}
+
+fn tick_countdown(mut commands: Commands, time: Res<Time>, mut countdown: ResMut<Countdown>) {
+    if countdown.tick(time.delta()).just_finished() {
+        commands.insert_resource(NextState(GameState::Playing));
+    }
+}
+
+fn cleanup_lobby(mut commands: Commands, mut lobby: ResMut<Lobby>) {
+    commands.remove_resource::<Countdown>();
+    lobby.host_ready = false;
+    lobby.client_ready = false;
+}
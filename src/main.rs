@@ -1,20 +1,33 @@
-use std::time::Duration;
+use std::{path::PathBuf, time::Duration};
 
+use bevy::asset::FileAssetIo;
 use bevy::core_pipeline::bloom::BloomSettings;
 use bevy::prelude::*;
+use iyes_loopless::fixedtimestep::FixedTimesteps;
 use iyes_loopless::prelude::{
     AppLooplessFixedTimestepExt, AppLooplessStateExt, ConditionSet, IntoConditionalSystem,
 };
-use network::{NetworkState, ClientResource};
+use network::{ClientResource, NetworkState};
 use serde::{Deserialize, Serialize};
 use tetris::*;
 
+mod bot;
+mod chat;
+mod garbage;
+mod lobby;
+mod modes;
 mod movement;
 mod network;
+mod pieces;
+mod replay;
+mod series;
 mod tetris;
 mod ui;
 mod visuals;
 
+#[cfg(test)]
+mod tests;
+
 #[rustfmt::skip]
 fn main() {
     App::new()
@@ -35,78 +48,208 @@ fn main() {
         // .add_plugin(bevy::diagnostic::LogDiagnosticsPlugin::default())
         // .add_plugin(bevy::diagnostic::FrameTimeDiagnosticsPlugin::default())
 
-        .add_loopless_state(GameState::Menu)
-        .add_loopless_state(NetworkState::default())
-
+        .add_plugin(GamePlugin)
         .add_plugin(ui::UiPlugin)
-        .add_plugin(network::NetworkPlugin)
-
-        .add_startup_system(setup)
-
-        // Playing
-        .add_enter_system(GameState::Playing, game_setup)
-        .add_system_set(
-            ConditionSet::new()
-                .run_in_state(GameState::Playing)
-                .with_system(movement::player_input)
-                // .with_system(movement::move_piece.run_if_resource_exists::<CurrentPiece>())
-                .with_system(visuals::draw_falling.run_if_resource_exists::<CurrentPiece>())
-                .with_system(visuals::draw_tiles)
-                .with_system(tetris::spawn_piece.run_unless_resource_exists::<CurrentPiece>())
-                .with_system(tetris::clear_lines.run_if_resource_removed::<CurrentPiece>())
-                .into()
-        )
-        .add_system_set(
-            ConditionSet::new()
-                .run_in_state(GameState::Playing)
-                .run_if_resource_exists::<CurrentPiece>()
-                .run_if_resource_exists::<ClientResource>()
-                .with_system(movement::move_piece)
-                .into()
-        )
 
-        .add_fixed_timestep(Duration::from_millis(1000), "gravity")
-        .add_fixed_timestep_system_set("gravity", 0,
-            ConditionSet::new()
-                .run_in_state(GameState::Playing)
-                .with_system(movement::tetris_gravity)
-                .into()
-        )
+        .run();
+}
 
-        .add_event::<movement::TetrisMoveEvent>()
+/// Everything but the window and the menus, so the game can also run
+/// headless in tests
+pub struct GamePlugin;
+impl Plugin for GamePlugin {
+    #[rustfmt::skip]
+    fn build(&self, app: &mut App) {
+        app
+            .add_loopless_state(GameState::Menu)
+            .add_loopless_state(NetworkState::default())
+            .insert_resource(GameSettings::default())
+            .insert_resource(movement::Handling::default())
+            .insert_resource(visuals::Theme::default())
 
-        .run();
+            .add_plugin(network::NetworkPlugin)
+            .add_plugin(lobby::LobbyPlugin)
+            .add_plugin(series::SeriesPlugin)
+            .add_plugin(chat::ChatPlugin)
+            .add_plugin(garbage::GarbagePlugin)
+            .add_plugin(replay::ReplayPlugin)
+            .add_plugin(bot::BotPlugin)
+            .add_plugin(modes::ModesPlugin)
+
+            .add_startup_system(setup)
+
+            // Playing
+            .add_enter_system(GameState::Playing, game_setup)
+            .add_exit_system(GameState::Playing, game_cleanup)
+            .add_enter_system(GameState::Replay, game_setup)
+            .add_exit_system(GameState::Replay, game_cleanup)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Playing)
+                    .with_system(movement::player_input.run_if(chat::chat_closed))
+                    // .with_system(movement::move_piece.run_if_resource_exists::<CurrentPiece>())
+                    .with_system(visuals::draw_falling.run_if_resource_exists::<CurrentPiece>())
+                    .with_system(visuals::draw_other_falling)
+                    .with_system(visuals::draw_tiles)
+                    .with_system(tetris::spawn_piece.run_unless_resource_exists::<CurrentPiece>())
+                    .with_system(tetris::clear_lines.run_if_resource_removed::<CurrentPiece>())
+                    .with_system(tetris::update_game_time)
+                    .into()
+            )
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Playing)
+                    .run_if_resource_exists::<CurrentPiece>()
+                    .with_system(movement::move_piece)
+                    .into()
+            )
+
+            // Replay
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Replay)
+                    .with_system(visuals::draw_falling.run_if_resource_exists::<CurrentPiece>())
+                    .with_system(visuals::draw_other_falling)
+                    .with_system(visuals::draw_tiles)
+                    .into()
+            )
+
+            .add_fixed_timestep(Duration::from_millis(1000), "gravity")
+            .add_fixed_timestep_system_set("gravity", 0,
+                ConditionSet::new()
+                    .run_in_state(GameState::Playing)
+                    .with_system(movement::tetris_gravity.run_if(modes::gravity_enabled))
+                    .with_system(bot::bot_gravity.run_if_resource_exists::<bot::BotPlayer>())
+                    .into()
+            )
+
+            .add_event::<movement::TetrisMoveEvent>()
+            .add_event::<ToppedOutEvent>()
+            .add_event::<PiecePlacedEvent>()
+            .add_event::<LinesClearedEvent>();
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum GameState {
     Menu,
     JoinMenu,
+    Lobby,
     Playing,
+    Results,
+    Replay,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
 pub enum GameMode {
     #[default]
     Normal,
     Hyper,
     Swap,
+    /// First to clear 40 lines
+    Sprint,
+    /// Highest score in two minutes
+    Ultra,
+    /// Levels up every 10 lines, getting faster until level 15
+    Marathon,
+    /// First to dig through the garbage at the bottom of the board
+    Cheese,
+    /// Both players on one wide board, needs another player
+    Coop,
+    /// Solve a board from a puzzle file with the pieces it gives
+    Puzzle,
+    /// No gravity or topping out, with undo, for practicing alone
+    Zen,
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 10] = [
+        GameMode::Normal,
+        GameMode::Hyper,
+        GameMode::Swap,
+        GameMode::Sprint,
+        GameMode::Ultra,
+        GameMode::Marathon,
+        GameMode::Cheese,
+        GameMode::Coop,
+        GameMode::Puzzle,
+        GameMode::Zen,
+    ];
+    /// Head to head modes where clearing lines sends garbage to the other player
+    pub fn sends_garbage(self) -> bool {
+        matches!(self, GameMode::Normal | GameMode::Hyper | GameMode::Swap)
+    }
+}
+
+/// Picked by the host in the lobby
+#[derive(Resource, Debug, Serialize, Deserialize, Clone)]
+pub struct GameSettings {
+    pub mode: GameMode,
+    /// Time between each gravity step
+    pub gravity: Duration,
+    /// Number of rounds in a series
+    pub best_of: u32,
+    /// Picked by the host for every round so both players get the same pieces
+    pub seed: u64,
+    /// Marathon level to start at
+    pub start_level: u32,
+    /// Marathon keeps going past the last level
+    pub endless: bool,
+    /// Garbage rows to dig through in cheese
+    pub cheese_rows: usize,
+    /// Columns and visible rows of each player's board
+    pub board_size: (usize, usize),
+    /// Loaded by the host like the puzzle so the client doesn't need the file
+    pub piece_set: pieces::PieceSet,
+    /// Loaded by the host so the client doesn't need the puzzle file
+    pub puzzle: Option<modes::Puzzle>,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        Self {
+            mode: GameMode::default(),
+            gravity: Duration::from_millis(1000),
+            best_of: 1,
+            seed: 0,
+            start_level: 1,
+            endless: false,
+            cheese_rows: 10,
+            board_size: STANDARD_BOARD_SIZE,
+            piece_set: pieces::PieceSet::standard(),
+            puzzle: None,
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum TetrisMove {
     Left,
     Right,
+    /// Gravity, locks the piece once it can't fall any further
     Fall,
+    /// Falls a row for a point without locking
+    SoftDrop,
+    /// Falls all the way for two points a row and locks straight away
+    HardDrop,
     RotateLeft,
     RotateRight,
 }
 
+/// A folder in the assets, found the same way the `AssetServer` finds them so
+/// it doesn't depend on where the game was started from
+pub fn asset_dir(dir: &str) -> PathBuf {
+    FileAssetIo::get_base_path().join("assets").join(dir)
+}
+
+/// Camera zoom that fits the usual board on screen
+const CAMERA_SCALE: f32 = 0.25;
+
 fn setup(mut commands: Commands) {
     commands.spawn((
         Camera2dBundle {
             projection: OrthographicProjection {
-                scale: 0.25,
+                scale: CAMERA_SCALE,
                 ..Default::default()
             },
             camera: Camera {
@@ -123,32 +266,123 @@ fn setup(mut commands: Commands) {
     ));
 }
 
-fn game_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let own_board = TetrisBoard::new([-60.0, 0.0].into());
-    let other_board = TetrisBoard::new([60.0, 0.0].into());
-    commands.insert_resource(TetrisPieceBuffer::new());
+fn game_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<GameSettings>,
+    client: Option<Res<ClientResource>>,
+    viewer: Option<Res<replay::ReplayViewer>>,
+    mut timesteps: ResMut<FixedTimesteps>,
+    mut cameras: Query<&mut OrthographicProjection>,
+) {
+    // A replay brings its own settings
+    let settings = viewer.as_ref().map_or(&*settings, |e| e.settings());
+    let gravity = timesteps
+        .get_mut("gravity")
+        .expect("Gravity timestep missing");
+    gravity.step = settings.gravity;
+    gravity.accumulator = Duration::ZERO;
+
+    // Playing alone only our own board is shown, in the middle
+    let solo = match &viewer {
+        Some(viewer) => viewer.player_count() < 2,
+        None => client.is_none(),
+    };
+    let coop = settings.mode == GameMode::Coop;
+    let (mut own_board, mut other_board) = if coop {
+        // The other board lines up with ours so the partner's piece is drawn
+        // on the shared board, the board itself stays empty
+        let offset = Vec2::ZERO;
+        (
+            TetrisBoard::for_settings(offset, settings),
+            TetrisBoard::for_settings(offset, settings),
+        )
+    } else {
+        // Side by side with a gap between them
+        let board = TetrisBoard::for_settings(Vec2::ZERO, settings);
+        let spacing = board_spacing(board.tiles.len());
+        (
+            TetrisBoard::for_settings([if solo { 0.0 } else { -spacing }, 0.0].into(), settings),
+            TetrisBoard::for_settings([spacing, 0.0].into(), settings),
+        )
+    };
+    // Zoom out so taller boards, and wider ones with everything next to
+    // them, still fit where two usual boards side by side do
+    let rows = own_board.height() - own_board.hidden_rows;
+    let columns = own_board.tiles.len();
+    let width = if solo || coop {
+        columns as f32 * 8.0
+    } else {
+        side_by_side_width(columns)
+    };
+    let zoom = (rows as f32 / STANDARD_BOARD_SIZE.1 as f32)
+        .max(width / side_by_side_width(STANDARD_BOARD_SIZE.0))
+        .max(1.0);
+    for mut projection in &mut cameras {
+        projection.scale = CAMERA_SCALE * zoom;
+    }
+
+    modes::prepare_board(settings, &mut own_board);
+    modes::prepare_board(settings, &mut other_board);
+    commands.insert_resource(GameStats::default());
+    commands.insert_resource(OtherGameStats::default());
+    commands.insert_resource(ClearChain::default());
+    commands.insert_resource(modes::piece_buffer(settings));
     commands.insert_resource(OwnTetrisBoard(own_board.clone()));
     commands.insert_resource(OtherTetrisBoard(other_board.clone()));
+    commands.insert_resource(OtherCurrentPiece::default());
 
     let mut spawn_board = |board: &TetrisBoard| {
-        commands.spawn(SpatialBundle::default()).with_children(|p| {
-            for x in 0..board.tiles.len() {
-                for y in 0..board.tiles[0].len() {
-                    let position = board.get_position([x as i32, y as i32].into());
-                    p.spawn(SpriteBundle {
-                        texture: asset_server.load("tetris_tile.png"),
-                        transform: Transform::from_translation(position),
-                        sprite: Sprite {
-                            color: Color::hsla(100.0, 0.0, 0.2, 0.4),
+        commands
+            .spawn((SpatialBundle::default(), BoardBackground))
+            .with_children(|p| {
+                for x in 0..board.tiles.len() {
+                    for y in board.hidden_rows..board.height() {
+                        let position = board.get_position([x as i32, y as i32].into());
+                        p.spawn(SpriteBundle {
+                            texture: asset_server.load("tetris_tile.png"),
+                            transform: Transform::from_translation(position),
+                            sprite: Sprite {
+                                color: Color::hsla(100.0, 0.0, 0.2, 0.4),
+                                ..Default::default()
+                            },
                             ..Default::default()
-                        },
-                        ..Default::default()
-                    });
+                        });
+                    }
                 }
-            }
-        });
+            });
     };
 
     spawn_board(&own_board);
-    spawn_board(&other_board);
+    if !solo && !coop {
+        spawn_board(&other_board);
+    }
+}
+
+/// How far each board's center is from the middle when two are side by side
+fn board_spacing(columns: usize) -> f32 {
+    columns as f32 * 4.0 + 20.0
+}
+
+/// Width of two boards side by side, from the left edge of one to the right
+/// edge of the other
+fn side_by_side_width(columns: usize) -> f32 {
+    board_spacing(columns) * 2.0 + columns as f32 * 8.0
+}
+
+#[derive(Component)]
+struct BoardBackground;
+
+fn game_cleanup(
+    mut commands: Commands,
+    query: Query<Entity, (Or<(With<Sprite>, With<BoardBackground>)>, Without<Parent>)>,
+) {
+    commands.remove_resource::<CurrentPiece>();
+    commands.remove_resource::<OwnTetrisBoard>();
+    commands.remove_resource::<OtherTetrisBoard>();
+    commands.remove_resource::<OtherCurrentPiece>();
+    commands.remove_resource::<TetrisPieceBuffer>();
+    for e in query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
 }
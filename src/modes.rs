@@ -0,0 +1,121 @@
+use std::{fs, time::Duration};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    pieces::PieceSet,
+    tetris::{GameStats, TetrisBoard, TetrisPieceBuffer},
+    GameMode, GameSettings,
+};
+
+mod cheese;
+mod coop;
+mod marathon;
+mod puzzle;
+mod sprint;
+mod ultra;
+mod zen;
+
+pub use cheese::{CheeseProgress, CHEESE_ROWS_OPTIONS};
+pub use coop::coop_spawn_offset;
+pub use marathon::{marathon_level, MarathonProgress, MARATHON_LEVELS};
+pub use puzzle::{Puzzle, PuzzleGoal, PuzzleProgress};
+pub use sprint::{SprintProgress, SPLIT_LINES, SPRINT_LINES};
+pub use ultra::{ultra_time_left, ULTRA_TIME};
+pub use zen::{gravity_enabled, ZenState};
+
+const PERSONAL_BESTS_PATH: &str = "personal_bests";
+
+pub struct ModesPlugin;
+impl Plugin for ModesPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(PersonalBests::load());
+        app.add_event::<GoalReachedEvent>();
+        app.add_event::<TimeUpEvent>();
+
+        app.add_plugin(sprint::SprintPlugin);
+        app.add_plugin(marathon::MarathonPlugin);
+        app.add_plugin(ultra::UltraPlugin);
+        app.add_plugin(cheese::CheesePlugin);
+        app.add_plugin(coop::CoopPlugin);
+        app.add_plugin(puzzle::PuzzlePlugin);
+        app.add_plugin(zen::ZenPlugin);
+    }
+}
+
+/// Sent when we complete the goal of the current mode
+pub struct GoalReachedEvent;
+
+/// Sent when the time limit of the current mode runs out, whoever has the
+/// highest score wins
+pub struct TimeUpEvent;
+
+/// Sets up a new board for the mode, both players get the same one
+pub fn prepare_board(settings: &GameSettings, board: &mut TetrisBoard) {
+    match (settings.mode, &settings.puzzle) {
+        (GameMode::Cheese, _) => cheese::fill_cheese(board, settings.cheese_rows, settings.seed),
+        (GameMode::Puzzle, Some(puzzle)) => puzzle.fill_board(board),
+        _ => {}
+    }
+}
+
+/// Puzzles come with their own pieces from the standard set, everything else
+/// gets random ones from the picked set
+pub fn piece_buffer(settings: &GameSettings) -> TetrisPieceBuffer {
+    match (settings.mode, &settings.puzzle) {
+        (GameMode::Puzzle, Some(puzzle)) => {
+            let set = PieceSet::standard();
+            let sequence = puzzle
+                .pieces
+                .iter()
+                .map(|e| set.pieces[*e].clone())
+                .collect();
+            TetrisPieceBuffer::with_sequence(settings.seed, set, sequence)
+        }
+        _ => TetrisPieceBuffer::new(settings.seed, settings.piece_set.clone()),
+    }
+}
+
+/// Checks if a player with these stats and board has completed the goal of
+/// the mode
+pub fn goal_reached(settings: &GameSettings, stats: &GameStats, board: &TetrisBoard) -> bool {
+    match settings.mode {
+        GameMode::Cheese => cheese::cheese_rows_left(board) == 0,
+        // T-spins are only known when the piece is placed, see `PuzzleProgress`
+        GameMode::Puzzle => match settings.puzzle.as_ref().map(|e| e.goal) {
+            Some(PuzzleGoal::Lines(lines)) => stats.lines >= lines,
+            Some(PuzzleGoal::PerfectClear) => stats.perfect_clears > 0,
+            _ => false,
+        },
+        GameMode::Sprint => stats.lines >= SPRINT_LINES,
+        GameMode::Marathon if !settings.endless => {
+            marathon_level(settings, stats) > MARATHON_LEVELS
+        }
+        _ => false,
+    }
+}
+
+fn mode_is(mode: GameMode) -> impl Fn(Res<GameSettings>) -> bool {
+    move |settings: Res<GameSettings>| settings.mode == mode
+}
+
+#[derive(Resource, Serialize, Deserialize, Default)]
+pub struct PersonalBests {
+    pub sprint: Option<Duration>,
+}
+
+impl PersonalBests {
+    fn load() -> Self {
+        fs::read(PERSONAL_BESTS_PATH)
+            .ok()
+            .and_then(|buf| bincode::deserialize(&buf).ok())
+            .unwrap_or_default()
+    }
+    fn save(&self) {
+        let buf = bincode::serialize(self).expect("Failed serializing personal bests");
+        if let Err(e) = fs::write(PERSONAL_BESTS_PATH, buf) {
+            println!("Failed saving personal bests: {e}");
+        }
+    }
+}
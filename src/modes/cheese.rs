@@ -0,0 +1,105 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{goal_reached, mode_is, GoalReachedEvent};
+use crate::{
+    tetris::{GameStats, OwnTetrisBoard, PieceKind, TetrisBoard, TetrisTile},
+    GameMode, GameSettings, GameState,
+};
+
+pub const CHEESE_ROWS_OPTIONS: [usize; 3] = [5, 10, 15];
+
+pub struct CheesePlugin;
+impl Plugin for CheesePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(GameState::Playing, setup_cheese);
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .run_if(mode_is(GameMode::Cheese))
+                .run_if_resource_exists::<CheeseProgress>()
+                .with_system(track_cheese)
+                .into(),
+        );
+    }
+}
+
+#[derive(Resource)]
+pub struct CheeseProgress {
+    pub rows_left: usize,
+    pub finished: bool,
+}
+
+/// Fills the bottom rows with garbage, every row has at least one hole and
+/// the holes move around so they can't all be cleared down a single column
+pub fn fill_cheese(board: &mut TetrisBoard, rows: usize, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let width = board.tiles.len();
+    let height = board.tiles[0].len();
+
+    let mut last_holes = vec![];
+    for y in height.saturating_sub(rows)..height {
+        let count = rng.gen_range(1..=2);
+        let mut holes = vec![];
+        while holes.len() < count {
+            let x = rng.gen_range(0..width);
+            if !holes.contains(&x) && !last_holes.contains(&x) {
+                holes.push(x);
+            }
+        }
+
+        for x in 0..width {
+            let tile = match holes.contains(&x) {
+                true => None,
+                false => Some(TetrisTile {
+                    kind: PieceKind::Garbage,
+                }),
+            };
+            board.set([x as i32, y as i32].into(), tile);
+        }
+        last_holes = holes;
+    }
+}
+
+/// Rows that still have garbage in them
+pub fn cheese_rows_left(board: &TetrisBoard) -> usize {
+    (0..board.tiles[0].len())
+        .filter(|y| {
+            board
+                .tiles
+                .iter()
+                .any(|col| col[*y].map_or(false, |e| e.kind == PieceKind::Garbage))
+        })
+        .count()
+}
+
+fn setup_cheese(mut commands: Commands, settings: Res<GameSettings>) {
+    if settings.mode == GameMode::Cheese {
+        commands.insert_resource(CheeseProgress {
+            rows_left: settings.cheese_rows,
+            finished: false,
+        });
+    } else {
+        commands.remove_resource::<CheeseProgress>();
+    }
+}
+
+fn track_cheese(
+    settings: Res<GameSettings>,
+    stats: Res<GameStats>,
+    board: Res<OwnTetrisBoard>,
+    mut progress: ResMut<CheeseProgress>,
+    mut goal: EventWriter<GoalReachedEvent>,
+) {
+    if progress.finished || !board.is_changed() {
+        return;
+    }
+
+    progress.rows_left = cheese_rows_left(&board);
+    if goal_reached(&settings, &stats, &board) {
+        progress.finished = true;
+        goal.send(GoalReachedEvent);
+    }
+}
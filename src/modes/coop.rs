@@ -0,0 +1,92 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use super::mode_is;
+use crate::{
+    network::{ClientMessage, ClientResource, NetworkState},
+    tetris::{
+        clear_board, ClearChain, CurrentPiece, GameStats, LinesClearedEvent, OwnTetrisBoard,
+        PiecePlacedEvent, ToppedOutEvent,
+    },
+    GameMode, GameState,
+};
+
+pub struct CoopPlugin;
+impl Plugin for CoopPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .run_in_state(NetworkState::Client)
+                .run_if(mode_is(GameMode::Coop))
+                .run_if_resource_exists::<ClientResource>()
+                .with_system(send_placements)
+                .into(),
+        );
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .run_in_state(NetworkState::Host)
+                .run_if(mode_is(GameMode::Coop))
+                .run_if_resource_exists::<OwnTetrisBoard>()
+                .with_system(apply_partner_placements)
+                .into(),
+        );
+    }
+}
+
+/// The host spawns its pieces on the left half of the shared board and the
+/// client on the right
+pub fn coop_spawn_offset(network_state: NetworkState, width: usize) -> i32 {
+    match network_state {
+        NetworkState::Client => width as i32 / 2,
+        _ => 0,
+    }
+}
+
+fn send_placements(mut placed: EventReader<PiecePlacedEvent>, mut client: ResMut<ClientResource>) {
+    for e in placed.iter() {
+        client.send(ClientMessage::CoopPlace(e.piece.tiles.clone(), e.t_spin));
+    }
+}
+
+/// The host owns the shared board, the client's pieces are placed on it once
+/// they arrive and clear lines like our own. The result goes back with the
+/// board updates
+fn apply_partner_placements(
+    mut messages: EventReader<ClientMessage>,
+    mut board: ResMut<OwnTetrisBoard>,
+    mut stats: ResMut<GameStats>,
+    mut chain: ResMut<ClearChain>,
+    piece: Option<Res<CurrentPiece>>,
+    mut cleared: EventWriter<LinesClearedEvent>,
+    mut topped_out: EventWriter<ToppedOutEvent>,
+) {
+    for message in messages.iter() {
+        if let ClientMessage::CoopPlace(tiles, t_spin) = message {
+            // Landed where one of our pieces got placed in the meantime, it
+            // is dropped and the client gets our board back without it
+            if tiles.iter().any(|(pos, _)| !board.tile_empty(*pos)) {
+                println!("Dropped a co-op placement that overlaps the board");
+                board.set_changed();
+                continue;
+            }
+            for (pos, tile) in tiles {
+                board.set(*pos, Some(*tile));
+            }
+            stats.pieces += 1;
+            let event = clear_board(&mut board, &mut chain, *t_spin);
+            stats.add_clear(&event);
+            cleared.send(event);
+
+            // Our piece may now be stuck inside the partner's
+            let blocked = piece.as_ref().map_or(false, |piece| {
+                piece.tiles.iter().any(|(pos, _)| !board.tile_empty(*pos))
+            });
+            if blocked {
+                topped_out.send(ToppedOutEvent);
+            }
+        }
+    }
+}
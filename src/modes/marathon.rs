@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use iyes_loopless::{fixedtimestep::FixedTimesteps, prelude::*};
+
+use super::{goal_reached, mode_is, GoalReachedEvent};
+use crate::{
+    tetris::{GameStats, OwnTetrisBoard},
+    GameMode, GameSettings, GameState,
+};
+
+/// Clearing this level finishes the game unless playing endless
+pub const MARATHON_LEVELS: u32 = 15;
+const LEVEL_LINES: usize = 10;
+
+pub struct MarathonPlugin;
+impl Plugin for MarathonPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(GameState::Playing, setup_marathon);
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .run_if(mode_is(GameMode::Marathon))
+                .run_if_resource_exists::<MarathonProgress>()
+                .with_system(track_marathon)
+                .into(),
+        );
+    }
+}
+
+#[derive(Resource)]
+pub struct MarathonProgress {
+    pub level: u32,
+    pub finished: bool,
+}
+
+pub fn marathon_level(settings: &GameSettings, stats: &GameStats) -> u32 {
+    settings.start_level + (stats.lines / LEVEL_LINES) as u32
+}
+
+/// Guideline gravity, the time it takes a piece to fall one row
+fn level_gravity(level: u32) -> Duration {
+    let level = level.saturating_sub(1) as f32;
+    Duration::from_secs_f32((0.8 - level * 0.007).powf(level))
+}
+
+fn setup_marathon(mut commands: Commands, settings: Res<GameSettings>) {
+    if settings.mode == GameMode::Marathon {
+        commands.insert_resource(MarathonProgress {
+            level: settings.start_level,
+            finished: false,
+        });
+    } else {
+        commands.remove_resource::<MarathonProgress>();
+    }
+}
+
+fn track_marathon(
+    settings: Res<GameSettings>,
+    stats: Res<GameStats>,
+    board: Res<OwnTetrisBoard>,
+    mut progress: ResMut<MarathonProgress>,
+    mut timesteps: ResMut<FixedTimesteps>,
+    mut goal: EventWriter<GoalReachedEvent>,
+) {
+    if progress.finished {
+        return;
+    }
+
+    if goal_reached(&settings, &stats, &board) {
+        progress.finished = true;
+        goal.send(GoalReachedEvent);
+        return;
+    }
+
+    let level = marathon_level(&settings, &stats);
+    // The lobby gravity is set when the game starts so replace it right away
+    if level != progress.level || progress.is_added() {
+        progress.level = level;
+        let gravity = timesteps
+            .get_mut("gravity")
+            .expect("Gravity timestep missing");
+        gravity.step = level_gravity(level);
+    }
+}
@@ -0,0 +1,215 @@
+use std::fs;
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{goal_reached, mode_is, GoalReachedEvent};
+use crate::{
+    asset_dir,
+    tetris::{
+        CurrentPiece, GameStats, OwnTetrisBoard, PieceKind, PiecePlacedEvent, TSpin, TetrisBoard,
+        TetrisTile, ToppedOutEvent, STANDARD_BOARD_SIZE,
+    },
+    GameMode, GameSettings, GameState,
+};
+
+const PUZZLE_DIR: &str = "puzzles";
+/// Same order as the standard piece set
+const PIECE_NAMES: [char; 7] = ['T', 'S', 'Z', 'I', 'J', 'L', 'O'];
+
+pub struct PuzzlePlugin;
+impl Plugin for PuzzlePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(GameState::Playing, setup_puzzle);
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .run_if(mode_is(GameMode::Puzzle))
+                .run_if_resource_exists::<PuzzleProgress>()
+                .with_system(track_puzzle)
+                .into(),
+        );
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PuzzleGoal {
+    Lines(usize),
+    PerfectClear,
+    TSpin,
+}
+
+/// A starting board, the pieces to solve it with and what counts as solved.
+/// Picked by the host and sent to the client with the rest of the settings
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Puzzle {
+    pub name: String,
+    pub goal: PuzzleGoal,
+    /// Indices into the standard piece set in the order they are given
+    pub pieces: Vec<usize>,
+    pub tiles: Vec<IVec2>,
+}
+
+impl Puzzle {
+    /// Names of every puzzle file, sorted so they can be played in order
+    pub fn list() -> Vec<String> {
+        let entries = match fs::read_dir(asset_dir(PUZZLE_DIR)) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|e| e.extension().map_or(false, |e| e == "puzzle"))
+            .filter_map(|e| Some(e.file_stem()?.to_str()?.to_string()))
+            .collect();
+        names.sort();
+        names
+    }
+    pub fn load(name: &str) -> Result<Self, String> {
+        let path = asset_dir(PUZZLE_DIR).join(format!("{name}.puzzle"));
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed reading {}: {e}", path.display()))?;
+        Self::parse(name, &text).map_err(|e| format!("Failed parsing {}: {e}", path.display()))
+    }
+    /// A puzzle file is made of a goal line, a pieces line and the board
+    /// after a `board` line, where `X` is a tile and `.` is empty. The board
+    /// rows are placed at the bottom. Lines starting with `#` are ignored
+    ///
+    /// ```text
+    /// goal lines 2        (or `goal perfect_clear`, `goal t_spin`)
+    /// pieces I O T
+    /// board
+    /// XXXXXX..XX
+    /// ```
+    fn parse(name: &str, text: &str) -> Result<Self, String> {
+        let mut goal = None;
+        let mut pieces = vec![];
+        let mut rows = vec![];
+        let mut in_board = false;
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if in_board {
+                rows.push(line);
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("goal") => {
+                    goal = Some(match (words.next(), words.next()) {
+                        (Some("lines"), Some(n)) => PuzzleGoal::Lines(
+                            n.parse().map_err(|_| format!("Invalid line count {n}"))?,
+                        ),
+                        (Some("perfect_clear"), None) => PuzzleGoal::PerfectClear,
+                        (Some("t_spin"), None) => PuzzleGoal::TSpin,
+                        _ => return Err(format!("Invalid goal '{line}'")),
+                    })
+                }
+                Some("pieces") => {
+                    for piece in words {
+                        let index = PIECE_NAMES
+                            .iter()
+                            .position(|e| piece == e.to_string())
+                            .ok_or(format!("Unknown piece {piece}"))?;
+                        pieces.push(index);
+                    }
+                }
+                Some("board") => in_board = true,
+                _ => return Err(format!("Unexpected line '{line}'")),
+            }
+        }
+
+        let board = TetrisBoard::new(Vec2::ZERO, STANDARD_BOARD_SIZE);
+        let (width, height) = (board.tiles.len(), board.height());
+        let visible_rows = height - board.hidden_rows;
+        if rows.len() > visible_rows {
+            return Err(format!("The board has more than {visible_rows} rows"));
+        }
+        let mut tiles = vec![];
+        for (i, row) in rows.iter().enumerate() {
+            if row.chars().count() != width {
+                return Err(format!("Row '{row}' is not {width} tiles wide"));
+            }
+            let y = height - rows.len() + i;
+            for (x, c) in row.chars().enumerate() {
+                match c {
+                    'X' => tiles.push(IVec2::new(x as i32, y as i32)),
+                    '.' => {}
+                    _ => return Err(format!("Unknown tile '{c}'")),
+                }
+            }
+        }
+
+        if pieces.is_empty() {
+            return Err("No pieces given".to_string());
+        }
+        Ok(Self {
+            name: name.to_string(),
+            goal: goal.ok_or("No goal given")?,
+            pieces,
+            tiles,
+        })
+    }
+    pub fn fill_board(&self, board: &mut TetrisBoard) {
+        for tile in self.tiles.iter() {
+            board.set(
+                *tile,
+                Some(TetrisTile {
+                    kind: PieceKind::Garbage,
+                }),
+            );
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct PuzzleProgress {
+    /// None until the puzzle is solved or out of pieces
+    pub passed: Option<bool>,
+    t_spin: bool,
+}
+
+fn setup_puzzle(mut commands: Commands, settings: Res<GameSettings>) {
+    if settings.mode == GameMode::Puzzle {
+        commands.insert_resource(PuzzleProgress::default());
+    } else {
+        commands.remove_resource::<PuzzleProgress>();
+    }
+}
+
+fn track_puzzle(
+    settings: Res<GameSettings>,
+    stats: Res<GameStats>,
+    board: Res<OwnTetrisBoard>,
+    piece: Option<Res<CurrentPiece>>,
+    mut placed: EventReader<PiecePlacedEvent>,
+    mut progress: ResMut<PuzzleProgress>,
+    mut goal: EventWriter<GoalReachedEvent>,
+    mut topped_out: EventWriter<ToppedOutEvent>,
+) {
+    let puzzle = match (&settings.puzzle, progress.passed) {
+        (Some(puzzle), None) => puzzle,
+        _ => {
+            placed.clear();
+            return;
+        }
+    };
+
+    if puzzle.goal == PuzzleGoal::TSpin {
+        progress.t_spin |= placed.iter().any(|e| e.t_spin != TSpin::None);
+    }
+
+    if goal_reached(&settings, &stats, &board) || progress.t_spin {
+        progress.passed = Some(true);
+        goal.send(GoalReachedEvent);
+    } else if stats.pieces >= puzzle.pieces.len() && piece.is_some() {
+        // The next piece only spawns once the lines of the last one are cleared
+        progress.passed = Some(false);
+        topped_out.send(ToppedOutEvent);
+    }
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use super::{mode_is, GoalReachedEvent, PersonalBests};
+use crate::{tetris::GameStats, GameMode, GameSettings, GameState};
+
+pub const SPRINT_LINES: usize = 40;
+pub const SPLIT_LINES: usize = 10;
+
+pub struct SprintPlugin;
+impl Plugin for SprintPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(GameState::Playing, setup_sprint);
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .run_if(mode_is(GameMode::Sprint))
+                .run_if_resource_exists::<SprintProgress>()
+                .with_system(track_sprint)
+                .into(),
+        );
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct SprintProgress {
+    /// The time every 10 lines was reached
+    pub splits: Vec<Duration>,
+    pub finished: bool,
+    /// The best time before this game
+    pub personal_best: Option<Duration>,
+    pub new_best: bool,
+}
+
+fn setup_sprint(mut commands: Commands, settings: Res<GameSettings>, pbs: Res<PersonalBests>) {
+    if settings.mode == GameMode::Sprint {
+        commands.insert_resource(SprintProgress {
+            personal_best: pbs.sprint,
+            ..default()
+        });
+    } else {
+        commands.remove_resource::<SprintProgress>();
+    }
+}
+
+fn track_sprint(
+    stats: Res<GameStats>,
+    mut progress: ResMut<SprintProgress>,
+    mut pbs: ResMut<PersonalBests>,
+    mut goal_reached: EventWriter<GoalReachedEvent>,
+) {
+    if progress.finished || !stats.is_changed() {
+        return;
+    }
+
+    while progress.splits.len() < stats.lines.min(SPRINT_LINES) / SPLIT_LINES {
+        progress.splits.push(stats.time);
+    }
+
+    if stats.lines >= SPRINT_LINES {
+        progress.finished = true;
+        if pbs.sprint.map_or(true, |pb| stats.time < pb) {
+            pbs.sprint = Some(stats.time);
+            pbs.save();
+            progress.new_best = true;
+        }
+        goal_reached.send(GoalReachedEvent);
+    }
+}
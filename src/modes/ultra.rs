@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use super::{mode_is, TimeUpEvent};
+use crate::{tetris::GameStats, GameMode, GameState};
+
+pub const ULTRA_TIME: Duration = Duration::from_secs(120);
+
+pub struct UltraPlugin;
+impl Plugin for UltraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(
+            track_ultra
+                .run_in_state(GameState::Playing)
+                .run_if(mode_is(GameMode::Ultra)),
+        );
+    }
+}
+
+/// Time left on the clock
+pub fn ultra_time_left(stats: &GameStats) -> Duration {
+    ULTRA_TIME.saturating_sub(stats.time)
+}
+
+fn track_ultra(
+    stats: Res<GameStats>,
+    mut last_time: Local<Duration>,
+    mut time_up: EventWriter<TimeUpEvent>,
+) {
+    // The stats are reset every round so this only happens once
+    if *last_time < ULTRA_TIME && stats.time >= ULTRA_TIME {
+        time_up.send(TimeUpEvent);
+    }
+    *last_time = stats.time;
+}
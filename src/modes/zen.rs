@@ -0,0 +1,123 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use super::{mode_is, GoalReachedEvent};
+use crate::{
+    tetris::{ClearChain, CurrentPiece, GameStats, OwnTetrisBoard, TetrisBoard, TetrisPieceBuffer},
+    GameMode, GameSettings, GameState,
+};
+
+const UNDO_LIMIT: usize = 100;
+
+pub struct ZenPlugin;
+impl Plugin for ZenPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(GameState::Playing, setup_zen);
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .run_if(mode_is(GameMode::Zen))
+                .run_if_resource_exists::<ZenState>()
+                .run_if_resource_exists::<OwnTetrisBoard>()
+                .with_system(zen_input)
+                .with_system(take_snapshots)
+                .into(),
+        );
+    }
+}
+
+/// Everything needed to go back to when a piece spawned
+#[derive(Clone)]
+struct Snapshot {
+    board: TetrisBoard,
+    piece: CurrentPiece,
+    buffer: TetrisPieceBuffer,
+    stats: GameStats,
+    chain: ClearChain,
+}
+
+#[derive(Resource, Default)]
+pub struct ZenState {
+    pub gravity: bool,
+    undo: VecDeque<Snapshot>,
+}
+
+/// Gravity can be turned off in zen
+pub fn gravity_enabled(zen: Option<Res<ZenState>>) -> bool {
+    zen.map_or(true, |e| e.gravity)
+}
+
+fn setup_zen(mut commands: Commands, settings: Res<GameSettings>) {
+    if settings.mode == GameMode::Zen {
+        commands.insert_resource(ZenState::default());
+    } else {
+        commands.remove_resource::<ZenState>();
+    }
+}
+
+fn take_snapshots(
+    piece: Option<Res<CurrentPiece>>,
+    board: Res<OwnTetrisBoard>,
+    buffer: Res<TetrisPieceBuffer>,
+    stats: Res<GameStats>,
+    chain: Res<ClearChain>,
+    mut zen: ResMut<ZenState>,
+    mut had_piece: Local<bool>,
+) {
+    if let Some(piece) = &piece {
+        if !*had_piece {
+            zen.undo.push_back(Snapshot {
+                board: board.0.clone(),
+                piece: (**piece).clone(),
+                buffer: buffer.clone(),
+                stats: stats.clone(),
+                chain: *chain,
+            });
+            if zen.undo.len() > UNDO_LIMIT {
+                zen.undo.pop_front();
+            }
+        }
+    }
+    *had_piece = piece.is_some();
+}
+
+fn zen_input(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    piece: Option<Res<CurrentPiece>>,
+    mut zen: ResMut<ZenState>,
+    mut board: ResMut<OwnTetrisBoard>,
+    mut buffer: ResMut<TetrisPieceBuffer>,
+    mut stats: ResMut<GameStats>,
+    mut chain: ResMut<ClearChain>,
+    mut goal: EventWriter<GoalReachedEvent>,
+) {
+    if keys.just_pressed(KeyCode::G) {
+        zen.gravity = !zen.gravity;
+    }
+    // Zen never ends on its own
+    if keys.just_pressed(KeyCode::Escape) {
+        goal.send(GoalReachedEvent);
+    }
+
+    if !keys.just_pressed(KeyCode::U) || piece.is_none() {
+        return;
+    }
+    // The last snapshot is of the current piece, the one before it is where
+    // the previous piece spawned
+    if zen.undo.len() > 1 {
+        zen.undo.pop_back();
+    }
+    if let Some(snapshot) = zen.undo.back().cloned() {
+        board.tiles = snapshot.board.tiles;
+        *buffer = snapshot.buffer;
+        let time = stats.time;
+        *stats = snapshot.stats;
+        stats.time = time;
+        *chain = snapshot.chain;
+        commands.insert_resource(snapshot.piece);
+    }
+}
@@ -1,103 +1,191 @@
-use crate::{tetris::*, TetrisMove};
+use std::time::Duration;
+
+use crate::{replay::ReplayRecorder, tetris::*, GameMode, GameSettings, TetrisMove};
 use bevy::prelude::*;
+use iyes_loopless::fixedtimestep::FixedTimesteps;
+
+pub const SOFT_DROP_FACTORS: [u32; 4] = [5, 10, 20, 40];
+pub const DAS_OPTIONS: [Duration; 5] = [
+    Duration::from_millis(100),
+    Duration::from_millis(133),
+    Duration::from_millis(167),
+    Duration::from_millis(200),
+    Duration::from_millis(250),
+];
+/// Zero moves all the way over at once
+pub const ARR_OPTIONS: [Duration; 4] = [
+    Duration::ZERO,
+    Duration::from_millis(16),
+    Duration::from_millis(33),
+    Duration::from_millis(50),
+];
+
+/// How the piece responds to held keys, everyone picks their own
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct Handling {
+    /// How many times faster than gravity soft drop is
+    pub soft_drop_factor: u32,
+    /// Delayed auto shift, how long left or right is held before it repeats
+    pub das: Duration,
+    /// Auto repeat rate, the time between each repeated move
+    pub arr: Duration,
+}
+
+impl Default for Handling {
+    fn default() -> Self {
+        Self {
+            soft_drop_factor: 20,
+            das: Duration::from_millis(167),
+            arr: Duration::from_millis(33),
+        }
+    }
+}
 
 pub fn move_piece(
     mut commands: Commands,
+    time: Res<Time>,
     mut move_events: EventReader<TetrisMoveEvent>,
     mut current_piece: ResMut<CurrentPiece>,
     mut board: ResMut<OwnTetrisBoard>,
+    mut recorder: ResMut<ReplayRecorder>,
+    mut stats: ResMut<GameStats>,
+    mut placed: EventWriter<PiecePlacedEvent>,
+    settings: Res<GameSettings>,
+    other_piece: Res<OtherCurrentPiece>,
 ) {
-    let mut stop_falling = false;
-    'move_loop: for m in move_events.iter() {
-        // Check if move allowed
-        for (p, _) in current_piece.tiles.iter() {
-            match m {
-                TetrisMove::Left => {
-                    if !board.tile_empty(*p - IVec2::X) {
-                        continue 'move_loop;
-                    }
-                }
-                TetrisMove::Right => {
-                    if !board.tile_empty(*p + IVec2::X) {
-                        continue 'move_loop;
-                    }
-                }
-                TetrisMove::Fall => {
-                    if !board.tile_empty(*p + IVec2::Y) {
-                        stop_falling = true;
-                        break 'move_loop;
-                    }
-                }
-                _ => {}
-            }
+    // In co-op the partner's piece is on our board and in the way too
+    let mut shared_board = None;
+    if settings.mode == GameMode::Coop && !other_piece.is_empty() {
+        let mut board = board.0.clone();
+        for (pos, tile) in other_piece.iter() {
+            board.set(*pos, Some(*tile));
         }
+        shared_board = Some(board);
+    }
+    let blocking = shared_board.as_ref().unwrap_or(&board.0);
 
-        if let TetrisMove::RotateLeft | TetrisMove::RotateRight = m {
-            // Check if rotaton allowed
-            let rotation = current_piece.rotation
-                + match m {
-                    TetrisMove::RotateLeft => 3,
-                    TetrisMove::RotateRight => 1,
-                    _ => 0,
-                };
-
-            for x in 0..4 {
-                for y in 0..4 {
-                    if current_piece.piece.value(rotation, x, y)
-                        && !board
-                            .tile_empty(IVec2::new(x as i32, y as i32) + current_piece.position)
-                    {
-                        continue 'move_loop;
-                    }
-                }
-            }
-
-            // If check passes, move the tiles
-            let color = current_piece.tiles[0].1.color;
-            current_piece.tiles.clear();
-
-            for x in 0..4 {
-                for y in 0..4 {
-                    if current_piece.piece.value(rotation, x, y) {
-                        let board_position =
-                            IVec2::new(x as i32, y as i32) + current_piece.position;
-                        current_piece
-                            .tiles
-                            .push((board_position, TetrisTile { color }));
-                    }
-                }
-            }
+    let mut stop_falling = false;
+    for m in move_events.iter() {
+        recorder.record(&time, *m);
+        let (dropped, lands) = play_move(&mut current_piece, blocking, *m);
+        stats.score += drop_points(*m, dropped);
+        if lands {
+            // Resting on the partner's piece doesn't count as landing
+            stop_falling = !apply_move(&mut current_piece.clone(), &board, TetrisMove::Fall);
+            break;
+        }
+    }
 
-            current_piece.rotation = rotation;
+    move_events.clear();
 
-            continue 'move_loop;
-        }
+    if stop_falling {
+        let t_spin = t_spin(&current_piece, &board);
+        board.place(&current_piece);
+        stats.pieces += 1;
+        placed.send(PiecePlacedEvent {
+            piece: current_piece.clone(),
+            t_spin,
+        });
+        commands.remove_resource::<CurrentPiece>();
+    }
+}
 
-        // If check passes, move the tiles
-        for tile in current_piece.tiles.iter_mut() {
-            match m {
-                TetrisMove::Left => tile.0 -= IVec2::X,
-                TetrisMove::Right => tile.0 += IVec2::X,
-                TetrisMove::Fall => tile.0 += IVec2::Y,
-                _ => {}
+/// Applies a move the way a player makes it, returns how many rows the
+/// player dropped the piece and if it landed and should lock
+pub fn play_move(piece: &mut CurrentPiece, board: &TetrisBoard, m: TetrisMove) -> (usize, bool) {
+    match m {
+        TetrisMove::HardDrop => {
+            let mut rows = 0;
+            while apply_move(piece, board, TetrisMove::Fall) {
+                rows += 1;
             }
+            (rows, true)
         }
-        match m {
-            TetrisMove::Left => current_piece.position -= IVec2::X,
-            TetrisMove::Right => current_piece.position += IVec2::X,
-            TetrisMove::Fall => current_piece.position += IVec2::Y,
-            _ => {}
+        TetrisMove::SoftDrop => (apply_move(piece, board, m) as usize, false),
+        TetrisMove::Fall => (0, !apply_move(piece, board, m)),
+        _ => {
+            apply_move(piece, board, m);
+            (0, false)
         }
     }
+}
 
-    move_events.clear();
+/// Brings a new piece onto the board, dropping it a row straight away if it
+/// can. Returns false if it spawned on top of something
+pub fn enter_board(piece: &mut CurrentPiece, board: &TetrisBoard) -> bool {
+    if piece.tiles.iter().any(|(pos, _)| !board.tile_empty(*pos)) {
+        return false;
+    }
+    apply_move(piece, board, TetrisMove::Fall);
+    true
+}
 
-    if stop_falling {
-        for (pos, tile) in current_piece.tiles.iter() {
-            board.set(*pos, Some(*tile));
+/// Moves the piece unless something is in the way, returns if it moved
+pub fn apply_move(current_piece: &mut CurrentPiece, board: &TetrisBoard, m: TetrisMove) -> bool {
+    // Check if move allowed
+    for (p, _) in current_piece.tiles.iter() {
+        let target = match m {
+            TetrisMove::Left => *p - IVec2::X,
+            TetrisMove::Right => *p + IVec2::X,
+            TetrisMove::Fall | TetrisMove::SoftDrop => *p + IVec2::Y,
+            _ => continue,
+        };
+        if !board.tile_empty(target) {
+            return false;
         }
-        commands.remove_resource::<CurrentPiece>();
     }
+
+    if let TetrisMove::RotateLeft | TetrisMove::RotateRight = m {
+        // Check if rotaton allowed
+        let rotation = current_piece.rotation
+            + match m {
+                TetrisMove::RotateLeft => 3,
+                TetrisMove::RotateRight => 1,
+                _ => 0,
+            };
+
+        let clockwise = matches!(m, TetrisMove::RotateRight);
+        let cells = current_piece.piece.cells(rotation);
+        let fits = |offset: IVec2| {
+            cells
+                .iter()
+                .all(|e| board.tile_empty(*e + current_piece.position + offset))
+        };
+        let kicks = wall_kicks(&current_piece.piece, current_piece.rotation, clockwise);
+        let kick = match kicks.iter().position(|e| fits(*e)) {
+            Some(kick) => kick,
+            None => return false,
+        };
+        current_piece.position += kicks[kick];
+
+        // If check passes, move the tiles
+        let kind = current_piece.piece.kind;
+        current_piece.tiles = cells
+            .iter()
+            .map(|e| (*e + current_piece.position, TetrisTile { kind }))
+            .collect();
+
+        current_piece.rotation = rotation;
+        current_piece.last_move = LastMove::Rotation(kick);
+
+        return true;
+    }
+
+    // If check passes, move the tiles
+    let offset = match m {
+        TetrisMove::Left => -IVec2::X,
+        TetrisMove::Right => IVec2::X,
+        TetrisMove::Fall | TetrisMove::SoftDrop => IVec2::Y,
+        // Hard drops are made of falls, see `play_move`
+        _ => return false,
+    };
+    for tile in current_piece.tiles.iter_mut() {
+        tile.0 += offset;
+    }
+    current_piece.position += offset;
+    current_piece.last_move = LastMove::Shift;
+
+    true
 }
 
 pub fn tetris_gravity(mut move_events: EventWriter<TetrisMoveEvent>) {
@@ -106,24 +194,263 @@ pub fn tetris_gravity(mut move_events: EventWriter<TetrisMoveEvent>) {
 
 pub type TetrisMoveEvent = TetrisMove;
 
-pub fn player_input(keys: Res<Input<KeyCode>>, mut move_events: EventWriter<TetrisMoveEvent>) {
+/// Left or right being held, kept between pieces so the charge carries over
+#[derive(Default)]
+pub struct AutoShift {
+    direction: Option<TetrisMove>,
+    held: Duration,
+    repeat: Duration,
+}
+
+pub fn player_input(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    handling: Res<Handling>,
+    timesteps: Res<FixedTimesteps>,
+    board: Res<OwnTetrisBoard>,
+    piece: Option<Res<CurrentPiece>>,
+    mut move_events: EventWriter<TetrisMoveEvent>,
+    mut shift: Local<AutoShift>,
+    mut soft_drop: Local<Duration>,
+) {
+    let rotated = keys.any_just_pressed([KeyCode::Q, KeyCode::Z, KeyCode::E, KeyCode::X]);
+    if keys.just_pressed(KeyCode::Q) || keys.just_pressed(KeyCode::Z) {
+        move_events.send(TetrisMove::RotateLeft);
+    }
+    if keys.just_pressed(KeyCode::E) || keys.just_pressed(KeyCode::X) {
+        move_events.send(TetrisMove::RotateRight);
+    }
+
+    // The direction pressed last wins while both are held, letting go of it
+    // goes back to the other one
+    let left_keys = [KeyCode::A, KeyCode::Left];
+    let right_keys = [KeyCode::D, KeyCode::Right];
+    let (left, right) = (keys.any_pressed(left_keys), keys.any_pressed(right_keys));
+    let direction = match (
+        keys.any_just_pressed(left_keys),
+        keys.any_just_pressed(right_keys),
+    ) {
+        (true, false) => Some(TetrisMove::Left),
+        (false, true) => Some(TetrisMove::Right),
+        _ => match shift.direction {
+            Some(TetrisMove::Left) if left => Some(TetrisMove::Left),
+            Some(TetrisMove::Right) if right => Some(TetrisMove::Right),
+            _ if left => Some(TetrisMove::Left),
+            _ if right => Some(TetrisMove::Right),
+            _ => None,
+        },
+    };
+    if direction != shift.direction {
+        *shift = AutoShift {
+            direction,
+            ..default()
+        };
+        if let Some(m) = direction {
+            move_events.send(m);
+        }
+    } else if let Some(m) = direction {
+        let charged = shift.held >= handling.das;
+        shift.held += time.delta();
+        if shift.held >= handling.das {
+            if handling.arr.is_zero() {
+                // All the way over, again whenever a new piece comes or the
+                // piece turns and might fit further
+                let new_piece = piece.as_ref().map_or(false, |e| e.is_added());
+                if !charged || new_piece || rotated {
+                    move_events.send_batch(std::iter::repeat(m).take(board.tiles.len()));
+                }
+            } else if !charged {
+                move_events.send(m);
+            } else {
+                shift.repeat += time.delta();
+                while shift.repeat >= handling.arr {
+                    move_events.send(m);
+                    shift.repeat -= handling.arr;
+                }
+            }
+        }
+    }
+
+    let soft_drop_keys = [KeyCode::S, KeyCode::Down];
+    if keys.any_just_pressed(soft_drop_keys) {
+        move_events.send(TetrisMove::SoftDrop);
+        *soft_drop = Duration::ZERO;
+    } else if keys.any_pressed(soft_drop_keys) {
+        let gravity = timesteps
+            .get("gravity")
+            .map_or(Duration::from_secs(1), |e| e.step);
+        let interval = gravity / handling.soft_drop_factor;
+        *soft_drop += time.delta();
+        while *soft_drop >= interval {
+            move_events.send(TetrisMove::SoftDrop);
+            *soft_drop -= interval;
+        }
+    }
+
     // Based on this post https://www.reddit.com/r/Tetris/comments/8viwld/comment/e5kcgr7/?utm_source=share&utm_medium=web2x&context=3
     if keys.just_pressed(KeyCode::W) || keys.just_pressed(KeyCode::Up) {
-        move_events.send_batch([TetrisMove::Fall; 20])
+        move_events.send(TetrisMove::HardDrop);
     }
-    if keys.just_pressed(KeyCode::S) || keys.just_pressed(KeyCode::Down) {
-        move_events.send(TetrisMove::Fall);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        garbage::{attack_lines, exchange_garbage},
+        pieces::PieceSet,
+    };
+
+    fn piece_at(piece: &TetrisPiece, rotation: usize, position: IVec2) -> CurrentPiece {
+        let kind = piece.kind;
+        CurrentPiece {
+            piece: piece.clone(),
+            position,
+            rotation,
+            tiles: piece
+                .cells(rotation)
+                .iter()
+                .map(|e| (*e + position, TetrisTile { kind }))
+                .collect(),
+            last_move: LastMove::default(),
+        }
     }
-    if keys.just_pressed(KeyCode::A) || keys.just_pressed(KeyCode::Left) {
-        move_events.send(TetrisMove::Left);
+
+    fn positions(piece: &CurrentPiece) -> Vec<IVec2> {
+        let mut positions: Vec<IVec2> = piece.tiles.iter().map(|(pos, _)| *pos).collect();
+        positions.sort_by_key(|e| (e.x, e.y));
+        positions
     }
-    if keys.just_pressed(KeyCode::D) || keys.just_pressed(KeyCode::Right) {
-        move_events.send(TetrisMove::Right);
+
+    #[test]
+    fn i_kicks_off_the_left_wall() {
+        let set = PieceSet::standard();
+        let board = TetrisBoard::new(Vec2::ZERO, STANDARD_BOARD_SIZE);
+        // Standing up against the wall in the L state, rotating in place
+        // would stick out of the board so the second kick moves it right
+        let mut piece = piece_at(&set.pieces[3], 0, IVec2::new(-1, 10));
+        assert!(apply_move(&mut piece, &board, TetrisMove::RotateRight));
+        assert_eq!(piece.last_move, LastMove::Rotation(1));
+        let expected: Vec<IVec2> = (0..4).map(|x| IVec2::new(x, 11)).collect();
+        assert_eq!(positions(&piece), expected);
     }
-    if keys.just_pressed(KeyCode::Q) || keys.just_pressed(KeyCode::Z) {
-        move_events.send(TetrisMove::RotateLeft);
+
+    #[test]
+    fn j_kicks_down_under_an_overhang() {
+        let set = PieceSet::standard();
+        let j = &set.pieces[4];
+        let start = IVec2::new(4, 10);
+        // The spawn state, and the R state two rows lower which the fourth
+        // kick from 0 to R reaches
+        let mut piece = piece_at(j, 1, start);
+        let target = piece_at(j, 2, start + IVec2::new(0, 2));
+
+        // Everything is taken but the piece and the slot under it
+        let mut board = TetrisBoard::new(Vec2::ZERO, STANDARD_BOARD_SIZE);
+        for x in 0..board.tiles.len() {
+            for y in 0..board.height() {
+                let pos = IVec2::new(x as i32, y as i32);
+                let free = piece.tiles.iter().chain(&target.tiles).any(|e| e.0 == pos);
+                if !free {
+                    board.set(
+                        pos,
+                        Some(TetrisTile {
+                            kind: PieceKind::Garbage,
+                        }),
+                    );
+                }
+            }
+        }
+
+        assert!(apply_move(&mut piece, &board, TetrisMove::RotateRight));
+        assert_eq!(piece.last_move, LastMove::Rotation(3));
+        assert_eq!(positions(&piece), positions(&target));
     }
-    if keys.just_pressed(KeyCode::E) || keys.just_pressed(KeyCode::X) {
-        move_events.send(TetrisMove::RotateRight);
+
+    fn fill(board: &mut TetrisBoard, cells: &[(i32, i32)]) {
+        for (x, y) in cells {
+            board.set(
+                IVec2::new(*x, *y),
+                Some(TetrisTile {
+                    kind: PieceKind::Garbage,
+                }),
+            );
+        }
+    }
+
+    /// A T pointing down with its center at (4, 10), as if it just turned
+    /// into place with the given kick
+    fn t_pointing_down(kick: usize) -> CurrentPiece {
+        let set = PieceSet::standard();
+        let mut piece = piece_at(&set.pieces[0], 1, IVec2::new(3, 9));
+        piece.last_move = LastMove::Rotation(kick);
+        piece
+    }
+
+    #[test]
+    fn t_spin_needs_three_corners_and_a_rotation() {
+        let mut board = TetrisBoard::new(Vec2::ZERO, STANDARD_BOARD_SIZE);
+        fill(&mut board, &[(3, 11), (5, 11)]);
+        assert_eq!(t_spin(&t_pointing_down(0), &board), TSpin::None);
+
+        fill(&mut board, &[(3, 9)]);
+        assert_eq!(t_spin(&t_pointing_down(0), &board), TSpin::Full);
+
+        let mut shifted = t_pointing_down(0);
+        shifted.last_move = LastMove::Shift;
+        assert_eq!(t_spin(&shifted, &board), TSpin::None);
+    }
+
+    #[test]
+    fn t_spin_mini_unless_both_front_corners_or_the_last_kick() {
+        // Only one of the corners the T points towards is taken
+        let mut board = TetrisBoard::new(Vec2::ZERO, STANDARD_BOARD_SIZE);
+        fill(&mut board, &[(3, 9), (5, 9), (3, 11)]);
+        assert_eq!(t_spin(&t_pointing_down(0), &board), TSpin::Mini);
+        assert_eq!(t_spin(&t_pointing_down(LAST_KICK), &board), TSpin::Full);
+    }
+
+    fn clear(lines: usize, t_spin: TSpin) -> LinesClearedEvent {
+        LinesClearedEvent {
+            lines,
+            t_spin,
+            combo: 0,
+            back_to_back: false,
+            perfect_clear: false,
+        }
+    }
+
+    #[test]
+    fn attack_lines_for_clears_and_t_spins() {
+        assert_eq!(attack_lines(&clear(1, TSpin::None)), 0);
+        assert_eq!(attack_lines(&clear(4, TSpin::None)), 4);
+        assert_eq!(attack_lines(&clear(1, TSpin::Mini)), 0);
+        assert_eq!(attack_lines(&clear(2, TSpin::Mini)), 1);
+        assert_eq!(attack_lines(&clear(2, TSpin::Full)), 4);
+
+        let chained = LinesClearedEvent {
+            combo: 2,
+            back_to_back: true,
+            ..clear(2, TSpin::Full)
+        };
+        assert_eq!(attack_lines(&chained), 6);
+        let perfect_clear = LinesClearedEvent {
+            perfect_clear: true,
+            ..clear(1, TSpin::None)
+        };
+        assert_eq!(attack_lines(&perfect_clear), 10);
+    }
+
+    #[test]
+    fn exchange_garbage_cancels_before_sending_and_rises_without_a_clear() {
+        let mut pending = 5;
+        assert_eq!(exchange_garbage(3, 1, &mut pending), (0, 0));
+        assert_eq!(pending, 2);
+        assert_eq!(exchange_garbage(4, 2, &mut pending), (2, 0));
+        assert_eq!(pending, 0);
+
+        pending = 3;
+        assert_eq!(exchange_garbage(0, 0, &mut pending), (0, 3));
+        assert_eq!(pending, 0);
     }
 }
@@ -1,23 +1,43 @@
+use std::time::Duration;
+
 use bevy::prelude::*;
-use iyes_loopless::prelude::{AppLooplessStateExt, ConditionSet, IntoConditionalSystem};
+use iyes_loopless::prelude::{
+    AppLooplessStateExt, ConditionSet, CurrentState, IntoConditionalSystem, NextState,
+};
 use local_ip_address::local_ip;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::{
-    io::{Read, Write},
-    net::{TcpListener, TcpStream},
-};
 
 use crate::{
-    tetris::{OtherTetrisBoard, OwnTetrisBoard, TetrisTile},
-    GameMode,
+    lobby::Lobby,
+    replay::ReplayEvent,
+    series::Series,
+    tetris::{
+        CurrentPiece, GameStats, OtherCurrentPiece, OtherGameStats, OtherTetrisBoard,
+        OwnTetrisBoard, TSpin, TetrisTile,
+    },
+    GameMode, GameSettings, GameState,
 };
 
+mod loopback;
+mod tcp;
+mod udp;
+
+pub use loopback::LoopbackTransport;
+pub use tcp::{TcpHostListener, TcpTransport};
+pub use udp::{UdpHostListener, UdpTransport};
+
 pub struct NetworkPlugin;
 impl Plugin for NetworkPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.insert_resource(HostAddress::default());
+        app.insert_resource(TransportKind::default());
+        app.insert_resource(Latency::default());
+
+        app.add_event::<HostMessage>();
+        app.add_event::<ClientMessage>();
 
         app.add_enter_system(NetworkState::Host, setup_host);
+        app.add_exit_system(NetworkState::Host, stop_host);
         app.add_enter_system(NetworkState::Client, setup_client);
 
         app.add_system_set(
@@ -27,8 +47,26 @@ impl Plugin for NetworkPlugin {
                 .into(),
         );
 
-        app.add_system(receive_messages.run_if_resource_exists::<ClientResource>());
-        app.add_system(send_board_updates.run_if_resource_exists::<ClientResource>());
+        app.add_system_set(
+            ConditionSet::new()
+                .run_if_resource_exists::<ClientResource>()
+                .with_system(handle_disconnect)
+                .with_system(receive_messages)
+                .with_system(send_pings)
+                .with_system(answer_pings)
+                .into(),
+        );
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_if_resource_exists::<ClientResource>()
+                .run_if_resource_exists::<OwnTetrisBoard>()
+                .with_system(apply_board_updates)
+                .with_system(send_board_updates)
+                .with_system(send_piece_updates)
+                .with_system(send_stats_updates)
+                .into(),
+        );
     }
 }
 
@@ -43,102 +81,326 @@ pub enum NetworkState {
 #[derive(Resource, Deref, DerefMut, Default)]
 pub struct HostAddress(pub String);
 
+/// Which transport the client connects with, the host accepts both
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransportKind {
+    Tcp,
+    #[default]
+    Udp,
+}
+
+/// Round trip time to the other player
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct Latency(pub Duration);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Resent until received and delivered in the order they were sent
+    ReliableOrdered,
+    /// Sent once, anything older than the last received message is dropped
+    UnreliableSequenced,
+}
+
+/// A connection to the other player which sends and receives whole messages
+pub trait Transport: Send + Sync {
+    fn send(&mut self, channel: Channel, message: &[u8]);
+    fn receive(&mut self) -> Vec<Vec<u8>>;
+    /// Runs every frame, used for things like resending lost packets
+    fn update(&mut self) {}
+    /// False once the other side is gone for good
+    fn is_connected(&self) -> bool {
+        true
+    }
+}
+
+/// Waits for the other player to connect to the host
+pub trait Listener: Send + Sync {
+    fn accept(&mut self) -> Option<Box<dyn Transport>>;
+}
+
 #[derive(Resource)]
 struct HostResource {
-    listener: TcpListener,
+    listeners: Vec<Box<dyn Listener>>,
 }
 
 #[derive(Resource)]
 pub struct ClientResource {
-    stream: TcpStream,
+    transport: Box<dyn Transport>,
+}
+
+impl ClientResource {
+    pub fn new(transport: impl Transport + 'static) -> Self {
+        Self {
+            transport: Box::new(transport),
+        }
+    }
+    pub fn send(&mut self, message: impl Into<Message>) {
+        let message = message.into();
+        let buf = bincode::serialize(&message).expect("Failed serializing message");
+        self.transport.send(message.channel(), &buf);
+    }
+    /// Messages that fail to deserialize are dropped
+    pub fn receive<T: DeserializeOwned>(&mut self) -> Vec<T> {
+        self.transport.update();
+        self.transport
+            .receive()
+            .iter()
+            .filter_map(|buf| match bincode::deserialize(buf) {
+                Ok(message) => Some(message),
+                Err(e) => {
+                    println!("Dropped a message that failed deserializing: {e}");
+                    None
+                }
+            })
+            .collect()
+    }
+    pub fn is_connected(&self) -> bool {
+        self.transport.is_connected()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Message {
+    Host(HostMessage),
+    Client(ClientMessage),
+}
+
+impl Message {
+    fn channel(&self) -> Channel {
+        match self {
+            Message::Client(ClientMessage::PieceUpdate(_))
+            | Message::Client(ClientMessage::Ping(_))
+            | Message::Client(ClientMessage::Pong(_)) => Channel::UnreliableSequenced,
+            _ => Channel::ReliableOrdered,
+        }
+    }
 }
 
+/// Only sent by the host
 #[derive(Serialize, Deserialize, Debug)]
-enum HostMessage {
-    Mode(GameMode),
+pub enum HostMessage {
+    Lobby(Lobby, GameSettings),
+    /// How long until the match starts, with the latency already taken off,
+    /// and the seed for the match
+    StartCountdown(Duration, u64),
+    RoundOver(Series),
 }
 
+impl From<HostMessage> for Message {
+    fn from(message: HostMessage) -> Self {
+        Message::Host(message)
+    }
+}
+
+/// Sent by both players
 #[derive(Serialize, Deserialize, Debug)]
-enum ClientMessage {
-    BoardUpdate([[Option<TetrisTile>; 20]; 10]),
+pub enum ClientMessage {
+    BoardUpdate(Vec<Vec<Option<TetrisTile>>>),
+    PieceUpdate(Vec<(IVec2, TetrisTile)>),
+    Ready(bool),
+    ToppedOut,
+    /// Completed the goal of the current mode
+    GoalReached,
+    /// The clock ran out, with the final score
+    TimeUp(usize),
+    Stats(GameStats),
+    /// A piece the client placed on the shared co-op board and if it was a
+    /// T-spin
+    CoopPlace(Vec<(IVec2, TetrisTile)>, TSpin),
+    /// Lines of garbage for the other player, after cancelling our own
+    Garbage(usize),
+    Chat(String),
+    ReplayEvents(Vec<(Duration, ReplayEvent)>),
+    Ping(Duration),
+    Pong(Duration),
+}
+
+impl From<ClientMessage> for Message {
+    fn from(message: ClientMessage) -> Self {
+        Message::Client(message)
+    }
 }
 
 fn setup_host(mut commands: Commands) {
-    let ip = local_ip()
-        .expect("Failed to get computers local Ip address")
-        .to_string();
-    let addr = format!("{ip}:8080");
-    let listener = TcpListener::bind(addr.clone()).expect("Failed creating TCP listener");
-    listener
-        .set_nonblocking(true)
-        .expect("Failed to enable non-blocking mode");
-    println!("Hosting TCP server at {addr}");
-    commands.insert_resource(HostResource { listener });
-}
-
-fn setup_client(mut commands: Commands, ip: Res<HostAddress>) {
-    // let ip = std::env::args()
-    //     .nth(2)
-    //     .expect("Please provide a IP to connect to");
+    let listeners = local_ip()
+        .map_err(|e| format!("Failed to get the computer's local IP address: {e}"))
+        .map(|ip| format!("{ip}:8080"))
+        .and_then(|addr| {
+            let tcp = TcpHostListener::bind(addr.clone())
+                .map_err(|e| format!("Failed creating TCP listener at {addr}: {e}"))?;
+            let udp = UdpHostListener::bind(addr.clone())
+                .map_err(|e| format!("Failed creating UDP socket at {addr}: {e}"))?;
+            println!("Hosting TCP and UDP server at {addr}");
+            Ok((tcp, udp))
+        });
+    let (tcp, udp) = match listeners {
+        Ok(listeners) => listeners,
+        Err(e) => {
+            println!("{e}");
+            commands.insert_resource(NextState(NetworkState::None));
+            commands.insert_resource(NextState(GameState::Menu));
+            return;
+        }
+    };
+    commands.insert_resource(HostResource {
+        listeners: vec![Box::new(tcp), Box::new(udp)],
+    });
+}
+
+fn setup_client(mut commands: Commands, ip: Res<HostAddress>, kind: Res<TransportKind>) {
     let addr = format!("{}:8080", ip.0);
-    let stream = TcpStream::connect(addr.clone()).expect("Failed to connect to TCP server");
-    stream
-        .set_nonblocking(true)
-        .expect("Failed to enable non-blocking mode");
-    println!("Connected to TCP server at {addr}");
-    commands.insert_resource(ClientResource { stream });
+    let transport: std::io::Result<Box<dyn Transport>> = match *kind {
+        TransportKind::Tcp => TcpTransport::connect(addr.clone()).map(|e| Box::new(e) as _),
+        TransportKind::Udp => UdpTransport::connect(addr.clone()).map(|e| Box::new(e) as _),
+    };
+    let transport = match transport {
+        Ok(transport) => transport,
+        Err(e) => {
+            println!("Failed to connect to {kind:?} server at {addr}: {e}");
+            commands.insert_resource(NextState(NetworkState::None));
+            commands.insert_resource(NextState(GameState::Menu));
+            return;
+        }
+    };
+    println!("Connecting to {kind:?} server at {addr}");
+    commands.insert_resource(ClientResource { transport });
+}
+
+/// Frees the port for the next time we host
+fn stop_host(mut commands: Commands) {
+    commands.remove_resource::<HostResource>();
+}
+
+fn check_for_connections(mut commands: Commands, mut host: ResMut<HostResource>) {
+    if let Some(transport) = host.listeners.iter_mut().find_map(|l| l.accept()) {
+        commands.insert_resource(ClientResource { transport });
+    }
+}
+
+/// The other player quit or the connection broke, back to the menu
+fn handle_disconnect(mut commands: Commands, client: Res<ClientResource>) {
+    if client.is_connected() {
+        return;
+    }
+    println!("Lost connection to the other player");
+    commands.remove_resource::<ClientResource>();
+    commands.remove_resource::<HostResource>();
+    commands.insert_resource(NextState(NetworkState::None));
+    commands.insert_resource(NextState(GameState::Menu));
 }
 
-fn check_for_connections(mut commands: Commands, host: Res<HostResource>) {
-    if let Some(Ok(stream)) = host.listener.incoming().next() {
-        // used to be .nth(0)
-        println!("Client connected from {}", stream.peer_addr().unwrap());
-        commands.insert_resource(ClientResource { stream });
+fn receive_messages(
+    mut client: ResMut<ClientResource>,
+    mut host_messages: EventWriter<HostMessage>,
+    mut client_messages: EventWriter<ClientMessage>,
+) {
+    for message in client.receive::<Message>() {
+        match message {
+            Message::Host(e) => host_messages.send(e),
+            Message::Client(e) => client_messages.send(e),
+        }
+    }
+}
+
+fn send_pings(time: Res<Time>, mut client: ResMut<ClientResource>, mut last_ping: Local<Duration>) {
+    if time.elapsed() - *last_ping < Duration::from_secs(1) {
+        return;
+    }
+    *last_ping = time.elapsed();
+    client.send(ClientMessage::Ping(time.elapsed()));
+}
+
+fn answer_pings(
+    time: Res<Time>,
+    mut messages: EventReader<ClientMessage>,
+    mut client: ResMut<ClientResource>,
+    mut latency: ResMut<Latency>,
+) {
+    for message in messages.iter() {
+        match message {
+            ClientMessage::Ping(e) => client.send(ClientMessage::Pong(*e)),
+            ClientMessage::Pong(e) => **latency = time.elapsed().saturating_sub(*e),
+            _ => {}
+        }
     }
 }
 
-fn receive_messages(mut client: ResMut<ClientResource>, mut other_board: ResMut<OtherTetrisBoard>) {
-    for message in deserialize_messages::<ClientMessage>(&mut client.stream) {
+fn apply_board_updates(
+    mut messages: EventReader<ClientMessage>,
+    settings: Res<GameSettings>,
+    network_state: Res<CurrentState<NetworkState>>,
+    mut own_board: ResMut<OwnTetrisBoard>,
+    mut other_board: ResMut<OtherTetrisBoard>,
+    mut other_piece: ResMut<OtherCurrentPiece>,
+    mut other_stats: ResMut<OtherGameStats>,
+) {
+    let coop = settings.mode == GameMode::Coop;
+    for message in messages.iter() {
         match message {
+            // In co-op the host owns the shared board
+            ClientMessage::BoardUpdate(e) if coop => {
+                if network_state.0 == NetworkState::Client {
+                    own_board.tiles = e.clone();
+                }
+            }
             ClientMessage::BoardUpdate(e) => {
-                other_board.tiles = e;
+                other_board.tiles = e.clone();
+            }
+            ClientMessage::PieceUpdate(e) => {
+                **other_piece = e.clone();
             }
+            ClientMessage::Stats(e) => {
+                **other_stats = e.clone();
+            }
+            _ => {}
         }
     }
 }
 
-fn send_board_updates(board: Res<OwnTetrisBoard>, mut client: ResMut<ClientResource>) {
+fn send_board_updates(
+    board: Res<OwnTetrisBoard>,
+    settings: Res<GameSettings>,
+    network_state: Res<CurrentState<NetworkState>>,
+    mut client: ResMut<ClientResource>,
+) {
     if !board.is_changed() {
         return;
     }
-    let buf = serialize_message(ClientMessage::BoardUpdate(board.0.tiles.to_owned()));
-    client
-        .stream
-        .write_all(&buf)
-        .expect("Failed to send board update");
-}
-
-fn serialize_message<T: Serialize>(msg: T) -> Vec<u8> {
-    let mut buf = bincode::serialize(&msg).expect("Failed serializing message");
-    let len = (buf.len() as u16).to_be_bytes();
-    buf.insert(0, len[0]);
-    buf.insert(1, len[1]);
-    buf
+    // The client sends its co-op pieces one by one instead
+    if settings.mode == GameMode::Coop && network_state.0 == NetworkState::Client {
+        return;
+    }
+    client.send(ClientMessage::BoardUpdate(board.0.tiles.to_owned()));
 }
 
-fn deserialize_messages<T: DeserializeOwned>(stream: &mut TcpStream) -> Vec<T> {
-    let mut messages = vec![];
-    loop {
-        let mut len_bytes = [0; 2];
-        if let Err(_) = stream.read_exact(&mut len_bytes) {
-            break;
+fn send_piece_updates(
+    piece: Option<Res<CurrentPiece>>,
+    mut client: ResMut<ClientResource>,
+    mut had_piece: Local<bool>,
+) {
+    let message = match &piece {
+        Some(piece) if piece.is_changed() => ClientMessage::PieceUpdate(piece.tiles.clone()),
+        // The piece was placed, it is now part of the board
+        None if *had_piece => ClientMessage::PieceUpdate(vec![]),
+        _ => {
+            *had_piece = piece.is_some();
+            return;
         }
-        let len = u16::from_be_bytes(len_bytes);
-        let mut buf = vec![0; len as usize];
-        stream.read_exact(&mut buf).expect("Failed reading body");
+    };
+    *had_piece = piece.is_some();
+    client.send(message);
+}
 
-        let message = bincode::deserialize::<T>(&buf[..]).expect("Failed deserializing message");
-        messages.push(message);
+/// The time changes every frame so only send when something else does
+fn send_stats_updates(
+    stats: Res<GameStats>,
+    mut client: ResMut<ClientResource>,
+    mut last_sent: Local<(usize, usize, usize)>,
+) {
+    let current = (stats.lines, stats.pieces, stats.score);
+    if current == *last_sent {
+        return;
     }
-    messages
+    *last_sent = current;
+    client.send(ClientMessage::Stats(stats.clone()));
 }
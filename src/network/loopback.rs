@@ -0,0 +1,49 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use super::{Channel, Transport};
+
+type Queue = Arc<Mutex<VecDeque<Vec<u8>>>>;
+
+/// An in-process transport, messages sent on one end of a pair are received
+/// on the other. Lets two apps in the same process talk without sockets.
+pub struct LoopbackTransport {
+    incoming: Queue,
+    outgoing: Queue,
+}
+
+impl LoopbackTransport {
+    pub fn pair() -> (Self, Self) {
+        let a = Queue::default();
+        let b = Queue::default();
+        (
+            Self {
+                incoming: a.clone(),
+                outgoing: b.clone(),
+            },
+            Self {
+                incoming: b,
+                outgoing: a,
+            },
+        )
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send(&mut self, _channel: Channel, message: &[u8]) {
+        self.outgoing
+            .lock()
+            .expect("Loopback queue poisoned")
+            .push_back(message.to_vec());
+    }
+
+    fn receive(&mut self) -> Vec<Vec<u8>> {
+        self.incoming
+            .lock()
+            .expect("Loopback queue poisoned")
+            .drain(..)
+            .collect()
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use super::{Channel, Listener, Transport};
+
+/// Every message is prefixed with its length as a big endian u16. Whatever
+/// doesn't fit in the socket's send buffer is kept and written on later frames.
+pub struct TcpTransport {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    unsent: Vec<u8>,
+    connected: bool,
+}
+
+impl TcpTransport {
+    pub fn connect(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+    fn new(stream: TcpStream) -> std::io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            buf: vec![],
+            unsent: vec![],
+            connected: true,
+        })
+    }
+    fn disconnect(&mut self, reason: &str) {
+        if self.connected {
+            println!("TCP connection closed: {reason}");
+        }
+        self.connected = false;
+    }
+    fn flush(&mut self) {
+        while self.connected && !self.unsent.is_empty() {
+            match self.stream.write(&self.unsent) {
+                Ok(0) => self.disconnect("stopped accepting data"),
+                Ok(len) => {
+                    self.unsent.drain(..len);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => self.disconnect(&e.to_string()),
+            }
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, _channel: Channel, message: &[u8]) {
+        let len = match u16::try_from(message.len()) {
+            Ok(len) => len,
+            Err(_) => {
+                println!(
+                    "Dropped a {} byte message, too large for TCP",
+                    message.len()
+                );
+                return;
+            }
+        };
+        self.unsent.extend_from_slice(&len.to_be_bytes());
+        self.unsent.extend_from_slice(message);
+        self.flush();
+    }
+
+    fn receive(&mut self) -> Vec<Vec<u8>> {
+        let mut chunk = [0; 4096];
+        while self.connected {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => self.disconnect("the other side quit"),
+                Ok(len) => self.buf.extend_from_slice(&chunk[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => self.disconnect(&e.to_string()),
+            }
+        }
+
+        let mut messages = vec![];
+        while self.buf.len() >= 2 {
+            let len = u16::from_be_bytes([self.buf[0], self.buf[1]]) as usize;
+            if self.buf.len() < len + 2 {
+                break;
+            }
+            messages.push(self.buf[2..len + 2].to_vec());
+            self.buf.drain(..len + 2);
+        }
+        messages
+    }
+
+    fn update(&mut self) {
+        self.flush();
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected
+    }
+}
+
+pub struct TcpHostListener {
+    listener: TcpListener,
+}
+
+impl TcpHostListener {
+    pub fn bind(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener })
+    }
+}
+
+impl Listener for TcpHostListener {
+    fn accept(&mut self) -> Option<Box<dyn Transport>> {
+        let (stream, addr) = self.listener.accept().ok()?;
+        println!("Client connected over TCP from {addr}");
+        match TcpTransport::new(stream) {
+            Ok(transport) => Some(Box::new(transport)),
+            Err(e) => {
+                println!("Failed to configure TCP stream: {e}");
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::{ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use super::{Channel, Listener, Transport};
+
+/// How long to wait for an ack before sending a reliable packet again.
+const RESEND_DELAY: Duration = Duration::from_millis(100);
+/// The other side is gone after this long without a packet, pings keep
+/// quiet connections alive
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_PACKET_SIZE: usize = 65507;
+
+// The first byte of every packet
+const HANDSHAKE: u8 = 0;
+const HANDSHAKE_ACK: u8 = 1;
+const RELIABLE: u8 = 2;
+const UNRELIABLE: u8 = 3;
+const ACK: u8 = 4;
+
+/// Reliable packets are resent until they are acked and handed out in the
+/// order they were sent. Unreliable packets are sent once and any packet
+/// older than the newest one received is dropped.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    connected: bool,
+    last_handshake: Instant,
+    last_received: Instant,
+    reliable_send_seq: u16,
+    reliable_recv_seq: u16,
+    pending_acks: HashMap<u16, (Instant, Vec<u8>)>,
+    out_of_order: HashMap<u16, Vec<u8>>,
+    unreliable_send_seq: u16,
+    unreliable_recv_seq: Option<u16>,
+}
+
+impl UdpTransport {
+    pub fn connect(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        let transport = Self::new(socket, false)?;
+        transport.send_raw(&[HANDSHAKE]);
+        Ok(transport)
+    }
+    fn new(socket: UdpSocket, connected: bool) -> std::io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            connected,
+            last_handshake: Instant::now(),
+            last_received: Instant::now(),
+            reliable_send_seq: 0,
+            reliable_recv_seq: 0,
+            pending_acks: HashMap::new(),
+            out_of_order: HashMap::new(),
+            unreliable_send_seq: 0,
+            unreliable_recv_seq: None,
+        })
+    }
+    fn send_raw(&self, packet: &[u8]) {
+        // Lost packets are handled by the reliability layer so errors
+        // like the other side not listening yet can be ignored
+        let _ = self.socket.send(packet);
+    }
+    fn handle_packet(&mut self, packet: &[u8], messages: &mut Vec<Vec<u8>>) {
+        self.connected = true;
+        self.last_received = Instant::now();
+        let (kind, rest) = match packet.split_first() {
+            Some((kind, rest)) => (*kind, rest),
+            None => return,
+        };
+        match kind {
+            HANDSHAKE => self.send_raw(&[HANDSHAKE_ACK]),
+            HANDSHAKE_ACK => {}
+            ACK if rest.len() == 2 => {
+                self.pending_acks
+                    .remove(&u16::from_be_bytes([rest[0], rest[1]]));
+            }
+            RELIABLE if rest.len() >= 2 => {
+                let seq = u16::from_be_bytes([rest[0], rest[1]]);
+                let mut ack = vec![ACK];
+                ack.extend_from_slice(&seq.to_be_bytes());
+                self.send_raw(&ack);
+
+                if seq == self.reliable_recv_seq {
+                    messages.push(rest[2..].to_vec());
+                    self.reliable_recv_seq = self.reliable_recv_seq.wrapping_add(1);
+                    while let Some(message) = self.out_of_order.remove(&self.reliable_recv_seq) {
+                        messages.push(message);
+                        self.reliable_recv_seq = self.reliable_recv_seq.wrapping_add(1);
+                    }
+                } else if sequence_greater(seq, self.reliable_recv_seq) {
+                    self.out_of_order.insert(seq, rest[2..].to_vec());
+                }
+            }
+            UNRELIABLE if rest.len() >= 2 => {
+                let seq = u16::from_be_bytes([rest[0], rest[1]]);
+                if self
+                    .unreliable_recv_seq
+                    .map_or(true, |last| sequence_greater(seq, last))
+                {
+                    self.unreliable_recv_seq = Some(seq);
+                    messages.push(rest[2..].to_vec());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, channel: Channel, message: &[u8]) {
+        // Checked before taking a sequence number so the receiver doesn't
+        // wait for a message that never comes
+        if message.len() + 3 > MAX_PACKET_SIZE {
+            println!(
+                "Dropped a {} byte message, too large for UDP",
+                message.len()
+            );
+            return;
+        }
+        let (kind, seq) = match channel {
+            Channel::ReliableOrdered => {
+                self.reliable_send_seq = self.reliable_send_seq.wrapping_add(1);
+                (RELIABLE, self.reliable_send_seq.wrapping_sub(1))
+            }
+            Channel::UnreliableSequenced => {
+                self.unreliable_send_seq = self.unreliable_send_seq.wrapping_add(1);
+                (UNRELIABLE, self.unreliable_send_seq.wrapping_sub(1))
+            }
+        };
+        let mut packet = vec![kind];
+        packet.extend_from_slice(&seq.to_be_bytes());
+        packet.extend_from_slice(message);
+
+        self.send_raw(&packet);
+        if kind == RELIABLE {
+            self.pending_acks.insert(seq, (Instant::now(), packet));
+        }
+    }
+
+    fn receive(&mut self) -> Vec<Vec<u8>> {
+        let mut messages = vec![];
+        let mut buf = vec![0; MAX_PACKET_SIZE];
+        loop {
+            match self.socket.recv(&mut buf) {
+                Ok(len) => self.handle_packet(&buf[..len], &mut messages),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // Usually the other side not listening (yet), try again next frame
+                Err(_) => break,
+            }
+        }
+        messages
+    }
+
+    fn update(&mut self) {
+        // Nobody is left to ack or send anything
+        if !self.is_connected() {
+            self.pending_acks.clear();
+            self.out_of_order.clear();
+            return;
+        }
+        let now = Instant::now();
+        if !self.connected && now - self.last_handshake > RESEND_DELAY {
+            self.last_handshake = now;
+            self.send_raw(&[HANDSHAKE]);
+        }
+        for (sent, packet) in self.pending_acks.values_mut() {
+            if now - *sent > RESEND_DELAY {
+                *sent = now;
+                let _ = self.socket.send(packet);
+            }
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.last_received.elapsed() < TIMEOUT
+    }
+}
+
+pub struct UdpHostListener {
+    socket: Option<UdpSocket>,
+}
+
+impl UdpHostListener {
+    pub fn bind(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket: Some(socket),
+        })
+    }
+}
+
+impl Listener for UdpHostListener {
+    fn accept(&mut self) -> Option<Box<dyn Transport>> {
+        let mut buf = [0; 1];
+        let (_, addr) = self.socket.as_ref()?.recv_from(&mut buf).ok()?;
+        if buf[0] != HANDSHAKE {
+            return None;
+        }
+        let socket = self.socket.take()?;
+        socket.connect(addr).ok()?;
+        println!("Client connected over UDP from {addr}");
+        let transport = UdpTransport::new(socket, true).expect("Failed to configure UDP socket");
+        transport.send_raw(&[HANDSHAKE_ACK]);
+        Some(Box::new(transport))
+    }
+}
+
+/// Compares sequence numbers while handling them wrapping around
+fn sequence_greater(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < u16::MAX / 2
+}
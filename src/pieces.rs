@@ -0,0 +1,290 @@
+use std::{collections::HashMap, fs};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    asset_dir,
+    tetris::{PieceKind, TetrisPiece, HIDDEN_ROWS},
+};
+
+const PIECE_SET_DIR: &str = "pieces";
+/// Big enough for pentominoes, the rotations still fit a `u32` each
+pub const MAX_PIECE_SIZE: usize = 5;
+
+/// SRS wall kicks tried in order until the rotated piece fits, as on the
+/// tetris wiki with y pointing up. Rows are 0->R, R->0, R->2, 2->R, 2->L,
+/// L->2, L->0 and 0->L
+const JLSTZ_KICKS: [[(i32, i32); 5]; 8] = [
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+];
+const I_KICKS: [[(i32, i32); 5]; 8] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+];
+
+/// The pieces a game is played with and how they are dealt. Picked by the
+/// host and sent to the client with the rest of the settings
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PieceSet {
+    pub name: String,
+    pub pieces: Vec<TetrisPiece>,
+    /// Indices into `pieces` that are shuffled and dealt before the next
+    /// bag, a piece can be in it more than once
+    pub bag: Vec<usize>,
+}
+
+impl Default for PieceSet {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+impl PieceSet {
+    /// The guideline tetrominoes with SRS kicks and a 7 bag
+    pub fn standard() -> Self {
+        // This post wwas a big help
+        // https://stackoverflow.com/a/38596291
+        let jlstz: Vec<_> = JLSTZ_KICKS.iter().map(|e| e.to_vec()).collect();
+        let i: Vec<_> = I_KICKS.iter().map(|e| e.to_vec()).collect();
+
+        // With the rotation that matches the guideline spawn orientation,
+        // flat side down and the 'T' pointing up. Every rotation is the SRS
+        // state so the kicks line up
+        #[rustfmt::skip]
+        let pieces = vec![
+            // degrees                                0       90      180     270
+            TetrisPiece::new(PieceKind::T, 3, 4, [0x4640, 0x0E40, 0x4C40, 0x4E00], jlstz.clone()),
+            TetrisPiece::new(PieceKind::S, 1, 4, [0x8C40, 0x6C00, 0x4620, 0x06C0], jlstz.clone()),
+            TetrisPiece::new(PieceKind::Z, 1, 4, [0x4C80, 0xC600, 0x2640, 0x0C60], jlstz.clone()),
+            TetrisPiece::new(PieceKind::I, 1, 4, [0x4444, 0x0F00, 0x2222, 0x00F0], i),
+            TetrisPiece::new(PieceKind::J, 1, 4, [0x44C0, 0x8E00, 0x6440, 0x0E20], jlstz.clone()),
+            TetrisPiece::new(PieceKind::L, 3, 4, [0x4460, 0x0E80, 0xC440, 0x2E00], jlstz),
+            TetrisPiece::new(PieceKind::O, 0, 4, [0xCC00, 0xCC00, 0xCC00, 0xCC00], vec![]),
+        ];
+        Self {
+            name: "standard".to_string(),
+            bag: (0..pieces.len()).collect(),
+            pieces,
+        }
+    }
+    /// Columns the widest piece needs in any rotation, narrower boards can't
+    /// fit it
+    pub fn width(&self) -> usize {
+        self.pieces
+            .iter()
+            .flat_map(|piece| (0..4).map(|rotation| piece.bounds(rotation)))
+            .map(|(min, max)| (max.x - min.x + 1) as usize)
+            .max()
+            .unwrap_or(0)
+    }
+    /// Rows above the visible board, enough for the biggest piece to spawn
+    /// and turn in
+    pub fn hidden_rows(&self) -> usize {
+        let size = self.pieces.iter().map(|e| e.size).max().unwrap_or(0);
+        size.max(HIDDEN_ROWS)
+    }
+    /// The row the top of every new piece spawns on, so the tallest one sits
+    /// just above the visible rows
+    pub fn spawn_row(&self) -> i32 {
+        let height = self
+            .pieces
+            .iter()
+            .map(|piece| piece.bounds(piece.spawn_rotation))
+            .map(|(min, max)| max.y - min.y + 1)
+            .max()
+            .unwrap_or(0);
+        self.hidden_rows() as i32 - height
+    }
+    /// Names of every piece set file, sorted
+    pub fn list() -> Vec<String> {
+        let entries = match fs::read_dir(asset_dir(PIECE_SET_DIR)) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|e| e.extension().map_or(false, |e| e == "pieces"))
+            .filter_map(|e| Some(e.file_stem()?.to_str()?.to_string()))
+            .collect();
+        names.sort();
+        names
+    }
+    pub fn load(name: &str) -> Result<Self, String> {
+        let path = asset_dir(PIECE_SET_DIR).join(format!("{name}.pieces"));
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed reading {}: {e}", path.display()))?;
+        Self::parse(name, &text).map_err(|e| format!("Failed parsing {}: {e}", path.display()))
+    }
+    /// A piece set file is made of kick tables, pieces and an optional bag.
+    /// A kick table is eight rows of offsets in the SRS order with y pointing
+    /// up. A piece is drawn in its spawn rotation on a square grid of up to
+    /// `MAX_PIECE_SIZE`, where `X` is a tile and `.` is empty, and the other
+    /// rotations turn the grid clockwise. Pieces without a kick table only
+    /// rotate in place. Without a bag every piece is dealt once per bag.
+    /// Lines starting with `#` are ignored
+    ///
+    /// ```text
+    /// kicks jlstz
+    /// 0,0 -1,0 -1,1 0,-2 -1,-2
+    /// ...
+    /// piece T jlstz
+    /// .X.
+    /// XXX
+    /// ...
+    /// bag T T I
+    /// ```
+    fn parse(name: &str, text: &str) -> Result<Self, String> {
+        let mut kick_tables: HashMap<&str, Vec<Vec<(i32, i32)>>> = HashMap::new();
+        let mut names = vec![];
+        let mut pieces = vec![];
+        let mut bag = vec![];
+        // The header line of the kick table or piece being read and its rows
+        let mut block: Option<(&str, Vec<&str>)> = None;
+
+        let lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|e| !e.is_empty() && !e.starts_with('#'));
+        for line in lines.chain(std::iter::once("end")) {
+            let mut words = line.split_whitespace();
+            let keyword = words.next();
+            if !matches!(keyword, Some("kicks" | "piece" | "bag" | "end")) {
+                match block.as_mut() {
+                    Some((_, rows)) => rows.push(line),
+                    None => return Err(format!("Unexpected line '{line}'")),
+                }
+                continue;
+            }
+
+            // A new line with a keyword finishes the block before it
+            if let Some((header, rows)) = block.take() {
+                let mut words = header.split_whitespace();
+                match (words.next(), words.next(), words.next()) {
+                    (Some("kicks"), Some(table), None) => {
+                        kick_tables.insert(table, parse_kicks(table, &rows)?);
+                    }
+                    (Some("piece"), Some(piece), table) => {
+                        let kicks = match table {
+                            Some(table) => kick_tables
+                                .get(table)
+                                .ok_or(format!("Unknown kick table {table}"))?
+                                .clone(),
+                            None => vec![],
+                        };
+                        let kind = PieceKind::Custom(
+                            u8::try_from(pieces.len()).map_err(|_| "Too many pieces")?,
+                        );
+                        pieces.push(parse_piece(piece, kind, kicks, &rows)?);
+                        names.push(piece);
+                    }
+                    _ => return Err(format!("Invalid line '{header}'")),
+                }
+            }
+
+            match keyword {
+                Some("bag") => {
+                    for piece in words {
+                        let index = names
+                            .iter()
+                            .position(|e| *e == piece)
+                            .ok_or(format!("Unknown piece {piece}"))?;
+                        bag.push(index);
+                    }
+                }
+                Some("end") => {}
+                _ => block = Some((line, vec![])),
+            }
+        }
+
+        if pieces.is_empty() {
+            return Err("No pieces given".to_string());
+        }
+        if bag.is_empty() {
+            bag = (0..pieces.len()).collect();
+        }
+        Ok(Self {
+            name: name.to_string(),
+            pieces,
+            bag,
+        })
+    }
+}
+
+fn parse_kicks(table: &str, rows: &[&str]) -> Result<Vec<Vec<(i32, i32)>>, String> {
+    if rows.len() != 8 {
+        return Err(format!("Kick table {table} doesn't have 8 rows"));
+    }
+    rows.iter()
+        .map(|row| {
+            row.split_whitespace()
+                .map(|kick| {
+                    let (x, y) = kick
+                        .split_once(',')
+                        .ok_or(format!("Invalid kick '{kick}'"))?;
+                    match (x.parse(), y.parse()) {
+                        (Ok(x), Ok(y)) => Ok((x, y)),
+                        _ => Err(format!("Invalid kick '{kick}'")),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn parse_piece(
+    piece: &str,
+    kind: PieceKind,
+    kicks: Vec<Vec<(i32, i32)>>,
+    rows: &[&str],
+) -> Result<TetrisPiece, String> {
+    let size = rows.len();
+    if size == 0 || size > MAX_PIECE_SIZE {
+        return Err(format!(
+            "Piece {piece} isn't 1 to {MAX_PIECE_SIZE} rows tall"
+        ));
+    }
+    let mut cells = vec![];
+    for (y, row) in rows.iter().enumerate() {
+        if row.chars().count() != size {
+            return Err(format!(
+                "Row '{row}' of piece {piece} is not {size} tiles wide"
+            ));
+        }
+        for (x, c) in row.chars().enumerate() {
+            match c {
+                'X' => cells.push((x, y)),
+                '.' => {}
+                _ => return Err(format!("Unknown tile '{c}'")),
+            }
+        }
+    }
+    if cells.is_empty() {
+        return Err(format!("Piece {piece} has no tiles"));
+    }
+
+    let mut data = [0; 4];
+    for (rotation, bits) in data.iter_mut().enumerate() {
+        for (x, y) in cells.iter().copied() {
+            let (mut x, mut y) = (x, y);
+            for _ in 0..rotation {
+                (x, y) = (size - 1 - y, x);
+            }
+            *bits |= TetrisPiece::bit(size, x, y);
+        }
+    }
+    Ok(TetrisPiece::new(kind, 0, size, data, kicks))
+}
@@ -0,0 +1,355 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    modes::{piece_buffer, prepare_board},
+    movement::{enter_board, play_move},
+    network::{ClientMessage, ClientResource},
+    tetris::*,
+    GameMode, GameSettings, GameState, TetrisMove,
+};
+
+/// Bump whenever the replay format or game rules change
+pub const REPLAY_VERSION: u32 = 11;
+const REPLAY_DIR: &str = "replays";
+const SEEK_STEP: Duration = Duration::from_secs(5);
+
+pub struct ReplayPlugin;
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ReplayRecorder::default());
+
+        app.add_enter_system(GameState::Playing, start_recording);
+        app.add_exit_system(GameState::Playing, save_replay);
+        app.add_exit_system(GameState::Replay, stop_replay);
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .with_system(send_replay_events)
+                .with_system(receive_replay_events)
+                .into(),
+        );
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Replay)
+                .run_if_resource_exists::<ReplayViewer>()
+                .with_system(replay_controls)
+                .with_system(advance_replay)
+                .into(),
+        );
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum ReplayEvent {
+    Move(TetrisMove),
+    /// Garbage rising up from the bottom with a hole in the given column
+    Garbage {
+        rows: usize,
+        hole: usize,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Replay {
+    pub version: u32,
+    pub settings: GameSettings,
+    /// Every event for each player with the time since the match started,
+    /// the first player is the one who recorded the replay
+    pub players: Vec<Vec<(Duration, ReplayEvent)>>,
+}
+
+impl Replay {
+    pub fn load_latest() -> Result<Self, String> {
+        let path = fs::read_dir(REPLAY_DIR)
+            .map_err(|e| format!("Failed reading replay directory: {e}"))?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|e| e.extension().map_or(false, |e| e == "replay"))
+            .max()
+            .ok_or("No replays found")?;
+        Self::load(path)
+    }
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let buf = fs::read(&path).map_err(|e| format!("Failed reading replay: {e}"))?;
+        let replay: Replay =
+            bincode::deserialize(&buf).map_err(|e| format!("Failed deserializing replay: {e}"))?;
+        if replay.version != REPLAY_VERSION {
+            return Err(format!(
+                "Replay version {} is not supported, expected {REPLAY_VERSION}",
+                replay.version
+            ));
+        }
+        Ok(replay)
+    }
+    fn save(&self) -> Result<PathBuf, String> {
+        fs::create_dir_all(REPLAY_DIR)
+            .map_err(|e| format!("Failed creating replay directory: {e}"))?;
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let path = PathBuf::from(REPLAY_DIR).join(format!("{millis}.replay"));
+        let buf =
+            bincode::serialize(self).map_err(|e| format!("Failed serializing replay: {e}"))?;
+        fs::write(&path, buf).map_err(|e| format!("Failed writing replay: {e}"))?;
+        Ok(path)
+    }
+}
+
+/// Records the moves of the current match, both our own and the ones the
+/// other player sends us
+#[derive(Resource, Default)]
+pub struct ReplayRecorder {
+    start: Duration,
+    own: Vec<(Duration, ReplayEvent)>,
+    unsent: Vec<(Duration, ReplayEvent)>,
+    other: Vec<(Duration, ReplayEvent)>,
+}
+
+impl ReplayRecorder {
+    pub fn record(&mut self, time: &Time, m: TetrisMove) {
+        self.record_event(time, ReplayEvent::Move(m));
+    }
+    pub fn record_event(&mut self, time: &Time, event: ReplayEvent) {
+        let event = (time.elapsed() - self.start, event);
+        self.own.push(event);
+        self.unsent.push(event);
+    }
+}
+
+fn start_recording(time: Res<Time>, mut recorder: ResMut<ReplayRecorder>) {
+    *recorder = ReplayRecorder {
+        start: time.elapsed(),
+        ..default()
+    };
+}
+
+fn send_replay_events(
+    mut recorder: ResMut<ReplayRecorder>,
+    client: Option<ResMut<ClientResource>>,
+) {
+    if recorder.unsent.is_empty() {
+        return;
+    }
+    let events = std::mem::take(&mut recorder.unsent);
+    if let Some(mut client) = client {
+        client.send(ClientMessage::ReplayEvents(events));
+    }
+}
+
+fn receive_replay_events(
+    mut messages: EventReader<ClientMessage>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    for message in messages.iter() {
+        if let ClientMessage::ReplayEvents(e) = message {
+            recorder.other.extend_from_slice(e);
+        }
+    }
+}
+
+fn save_replay(mut recorder: ResMut<ReplayRecorder>, settings: Res<GameSettings>) {
+    let recorder = std::mem::take(&mut *recorder);
+    // Each player is played back on their own board so a shared board can't
+    // be, and undoing in zen isn't recorded
+    if let GameMode::Coop | GameMode::Zen = settings.mode {
+        return;
+    }
+    let mut players = vec![recorder.own];
+    if !recorder.other.is_empty() {
+        players.push(recorder.other);
+    }
+    let replay = Replay {
+        version: REPLAY_VERSION,
+        settings: settings.clone(),
+        players,
+    };
+    match replay.save() {
+        Ok(path) => println!("Saved replay to {}", path.display()),
+        Err(e) => println!("{e}"),
+    }
+}
+
+/// Plays a single players events back on its own board
+struct PlayerSim {
+    board: TetrisBoard,
+    piece: CurrentPiece,
+    buffer: TetrisPieceBuffer,
+    next_event: usize,
+}
+
+impl PlayerSim {
+    fn new(settings: &GameSettings) -> Self {
+        let mut buffer = piece_buffer(settings);
+        let mut board = TetrisBoard::for_settings(Vec2::ZERO, settings);
+        prepare_board(settings, &mut board);
+        let mut piece = buffer.next_piece(board.tiles.len());
+        enter_board(&mut piece, &board);
+        Self {
+            board,
+            piece,
+            buffer,
+            next_event: 0,
+        }
+    }
+    fn apply(&mut self, event: ReplayEvent) {
+        match event {
+            ReplayEvent::Move(m) => {
+                if play_move(&mut self.piece, &self.board, m).1 {
+                    self.board.place(&self.piece);
+                    self.board.clear_lines();
+                    self.piece = self.buffer.next_piece(self.board.tiles.len());
+                    enter_board(&mut self.piece, &self.board);
+                }
+            }
+            ReplayEvent::Garbage { rows, hole } => {
+                self.board.add_garbage(rows, hole);
+            }
+        }
+    }
+    /// Applies every event up to the given time, returns if anything happened
+    fn advance(&mut self, events: &[(Duration, ReplayEvent)], time: Duration) -> bool {
+        let start = self.next_event;
+        while let Some((t, event)) = events.get(self.next_event) {
+            if *t > time {
+                break;
+            }
+            self.apply(*event);
+            self.next_event += 1;
+        }
+        self.next_event != start
+    }
+}
+
+#[derive(Resource)]
+pub struct ReplayViewer {
+    replay: Replay,
+    players: Vec<PlayerSim>,
+    pub time: Duration,
+    pub speed: f32,
+    pub paused: bool,
+    /// Set when seeking so the boards get redrawn
+    dirty: bool,
+}
+
+impl ReplayViewer {
+    pub fn new(replay: Replay) -> Self {
+        let players = replay
+            .players
+            .iter()
+            .map(|_| PlayerSim::new(&replay.settings))
+            .collect();
+        Self {
+            replay,
+            players,
+            time: Duration::ZERO,
+            speed: 1.0,
+            paused: false,
+            dirty: true,
+        }
+    }
+    /// The settings the replay was recorded with, the player's own are left
+    /// alone
+    pub fn settings(&self) -> &GameSettings {
+        &self.replay.settings
+    }
+    pub fn player_count(&self) -> usize {
+        self.replay.players.len()
+    }
+    pub fn duration(&self) -> Duration {
+        self.replay
+            .players
+            .iter()
+            .filter_map(|e| e.last())
+            .map(|(t, _)| *t)
+            .max()
+            .unwrap_or_default()
+    }
+    /// Going backwards plays everything again from the start
+    fn seek(&mut self, time: Duration) {
+        if time < self.time {
+            let settings = &self.replay.settings;
+            self.players
+                .iter_mut()
+                .for_each(|e| *e = PlayerSim::new(settings));
+        }
+        self.time = time.min(self.duration());
+        self.advance();
+        self.dirty = true;
+    }
+    fn advance(&mut self) -> bool {
+        let mut changed = false;
+        for (player, events) in self.players.iter_mut().zip(self.replay.players.iter()) {
+            changed |= player.advance(events, self.time);
+        }
+        changed
+    }
+}
+
+fn replay_controls(
+    keys: Res<Input<KeyCode>>,
+    mut commands: Commands,
+    mut viewer: ResMut<ReplayViewer>,
+) {
+    if keys.just_pressed(KeyCode::Escape) {
+        commands.insert_resource(NextState(GameState::Menu));
+    }
+    if keys.just_pressed(KeyCode::Space) {
+        viewer.paused = !viewer.paused;
+    }
+    if keys.just_pressed(KeyCode::Up) {
+        viewer.speed = (viewer.speed * 2.0).min(8.0);
+    }
+    if keys.just_pressed(KeyCode::Down) {
+        viewer.speed = (viewer.speed / 2.0).max(0.25);
+    }
+    if keys.just_pressed(KeyCode::Right) {
+        let time = viewer.time + SEEK_STEP;
+        viewer.seek(time);
+    }
+    if keys.just_pressed(KeyCode::Left) {
+        let time = viewer.time.saturating_sub(SEEK_STEP);
+        viewer.seek(time);
+    }
+}
+
+fn advance_replay(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut viewer: ResMut<ReplayViewer>,
+    mut own_board: ResMut<OwnTetrisBoard>,
+    mut other_board: ResMut<OtherTetrisBoard>,
+    mut other_piece: ResMut<OtherCurrentPiece>,
+) {
+    if !viewer.paused {
+        let end = viewer.duration();
+        viewer.time = (viewer.time + time.delta().mul_f32(viewer.speed)).min(end);
+    }
+    let dirty = std::mem::take(&mut viewer.dirty);
+    if !viewer.advance() && !dirty {
+        return;
+    }
+
+    if let Some(player) = viewer.players.get(0) {
+        own_board.tiles = player.board.tiles.clone();
+        commands.insert_resource(player.piece.clone());
+    }
+    if let Some(player) = viewer.players.get(1) {
+        other_board.tiles = player.board.tiles.clone();
+        **other_piece = player.piece.tiles.clone();
+    }
+}
+
+fn stop_replay(mut commands: Commands) {
+    commands.remove_resource::<ReplayViewer>();
+}
@@ -0,0 +1,223 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    lobby::Lobby,
+    modes::{GoalReachedEvent, TimeUpEvent},
+    network::{ClientMessage, ClientResource, HostMessage, NetworkState},
+    tetris::{GameStats, ToppedOutEvent},
+    GameMode, GameSettings, GameState,
+};
+
+pub struct SeriesPlugin;
+impl Plugin for SeriesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(GameState::Playing, start_round);
+        app.add_enter_system(GameState::Lobby, reset_finished_series);
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .run_not_in_state(NetworkState::Client)
+                .run_if_resource_exists::<ClientResource>()
+                .with_system(decide_round)
+                .into(),
+        );
+
+        app.add_system(
+            end_solo_round
+                .run_in_state(GameState::Playing)
+                .run_unless_resource_exists::<ClientResource>(),
+        );
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(NetworkState::Client)
+                .with_system(send_topped_out.run_in_state(GameState::Playing))
+                .with_system(receive_round_over)
+                .into(),
+        );
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Player {
+    Host,
+    Client,
+}
+
+/// Rounds won by each player, decided by the host
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Series {
+    pub host_wins: u32,
+    pub client_wins: u32,
+    /// None until the host has decided who won the current round
+    pub last_winner: Option<Player>,
+}
+
+impl Series {
+    pub fn winner(&self, best_of: u32) -> Option<Player> {
+        if self.host_wins > best_of / 2 {
+            Some(Player::Host)
+        } else if self.client_wins > best_of / 2 {
+            Some(Player::Client)
+        } else {
+            None
+        }
+    }
+    fn add_win(&mut self, player: Player) {
+        match player {
+            Player::Host => self.host_wins += 1,
+            Player::Client => self.client_wins += 1,
+        }
+        self.last_winner = Some(player);
+    }
+}
+
+/// Scores when each clock ran out, the round is decided once both are known
+#[derive(Resource, Default)]
+struct FinalScores {
+    host: Option<usize>,
+    client: Option<usize>,
+}
+
+fn start_round(mut commands: Commands, mut lobby: ResMut<Lobby>) {
+    lobby.series.last_winner = None;
+    commands.insert_resource(FinalScores::default());
+}
+
+fn reset_finished_series(mut lobby: ResMut<Lobby>, settings: Res<GameSettings>) {
+    if lobby.series.winner(settings.best_of).is_some() {
+        lobby.series = Series::default();
+    }
+}
+
+/// Whoever tops out first loses and whoever reaches the goal of the mode
+/// first wins, the host decides so both players agree
+fn decide_round(
+    mut commands: Commands,
+    mut topped_out: EventReader<ToppedOutEvent>,
+    mut goal_reached: EventReader<GoalReachedEvent>,
+    mut time_up: EventReader<TimeUpEvent>,
+    mut messages: EventReader<ClientMessage>,
+    mut lobby: ResMut<Lobby>,
+    mut client: ResMut<ClientResource>,
+    mut scores: ResMut<FinalScores>,
+    stats: Res<GameStats>,
+    settings: Res<GameSettings>,
+) {
+    let messages: Vec<&ClientMessage> = messages.iter().collect();
+    // In co-op nobody wins, the round is over as soon as either player tops out
+    if settings.mode == GameMode::Coop {
+        if topped_out.iter().next().is_some()
+            || messages
+                .iter()
+                .any(|e| matches!(e, ClientMessage::ToppedOut))
+        {
+            client.send(HostMessage::RoundOver(lobby.series.clone()));
+            commands.insert_resource(NextState(GameState::Results));
+        }
+        return;
+    }
+
+    if time_up.iter().next().is_some() {
+        scores.host = Some(stats.score);
+    }
+    for message in &messages {
+        if let ClientMessage::TimeUp(score) = message {
+            scores.client = Some(*score);
+        }
+    }
+
+    let winner = if goal_reached.iter().next().is_some() {
+        Player::Host
+    } else if let (Some(host), Some(client)) = (scores.host, scores.client) {
+        // There are no draws so the host keeps a tie
+        if client > host {
+            Player::Client
+        } else {
+            Player::Host
+        }
+    } else if scores.host.is_some() {
+        // Our clock ran out but the client's final score is still on its
+        // way, only the client topping out before its own clock ends it now
+        if messages
+            .iter()
+            .any(|e| matches!(e, ClientMessage::ToppedOut))
+        {
+            Player::Host
+        } else {
+            return;
+        }
+    } else if topped_out.iter().next().is_some() {
+        Player::Client
+    } else if let Some(message) = messages
+        .iter()
+        .find(|e| matches!(e, ClientMessage::ToppedOut | ClientMessage::GoalReached))
+    {
+        match message {
+            ClientMessage::GoalReached => Player::Client,
+            _ => Player::Host,
+        }
+    } else {
+        return;
+    };
+
+    lobby.series.add_win(winner);
+    client.send(HostMessage::RoundOver(lobby.series.clone()));
+    commands.insert_resource(NextState(GameState::Results));
+}
+
+fn send_topped_out(
+    mut commands: Commands,
+    mut topped_out: EventReader<ToppedOutEvent>,
+    mut goal_reached: EventReader<GoalReachedEvent>,
+    mut time_up: EventReader<TimeUpEvent>,
+    mut client: ResMut<ClientResource>,
+    stats: Res<GameStats>,
+) {
+    if goal_reached.iter().next().is_some() {
+        client.send(ClientMessage::GoalReached);
+    } else if time_up.iter().next().is_some() {
+        // The host waits for our final score before deciding
+        client.send(ClientMessage::Stats(stats.clone()));
+        client.send(ClientMessage::TimeUp(stats.score));
+    } else if topped_out.iter().next().is_some() {
+        client.send(ClientMessage::ToppedOut);
+    } else {
+        return;
+    }
+    commands.insert_resource(NextState(GameState::Results));
+}
+
+fn receive_round_over(
+    mut commands: Commands,
+    mut messages: EventReader<HostMessage>,
+    mut lobby: ResMut<Lobby>,
+    state: Res<CurrentState<GameState>>,
+) {
+    for message in messages.iter() {
+        if let HostMessage::RoundOver(series) = message {
+            lobby.series = series.clone();
+            if state.0 == GameState::Playing {
+                commands.insert_resource(NextState(GameState::Results));
+            }
+        }
+    }
+}
+
+/// Playing alone there is no one to decide anything
+fn end_solo_round(
+    mut commands: Commands,
+    mut topped_out: EventReader<ToppedOutEvent>,
+    mut goal_reached: EventReader<GoalReachedEvent>,
+    mut time_up: EventReader<TimeUpEvent>,
+) {
+    if topped_out.iter().next().is_some()
+        || goal_reached.iter().next().is_some()
+        || time_up.iter().next().is_some()
+    {
+        commands.insert_resource(NextState(GameState::Results));
+    }
+}
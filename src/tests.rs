@@ -0,0 +1,140 @@
+use bevy::{asset::AssetPlugin, input::InputPlugin, prelude::*};
+use iyes_loopless::prelude::*;
+
+use crate::{
+    garbage::PendingGarbage,
+    lobby::Lobby,
+    modes::ULTRA_TIME,
+    network::{ClientResource, LoopbackTransport, NetworkState},
+    series::Player,
+    tetris::*,
+    GameMode, GamePlugin, GameSettings, GameState, TetrisMove,
+};
+
+/// A game without a window, the way `main` builds it minus the menus
+fn headless_app() -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin::default())
+        .add_plugin(InputPlugin)
+        .add_event::<ReceivedCharacter>()
+        .add_plugin(GamePlugin);
+    app
+}
+
+/// A host and a client connected over a loopback transport, both playing
+/// the given mode
+fn connected_apps(mode: GameMode) -> (App, App) {
+    let (host_end, client_end) = LoopbackTransport::pair();
+
+    // The network states are set directly, their enter systems would open
+    // real sockets. Otherwise this is where hosting and joining end up once
+    // the client has connected
+    let mut host = headless_app();
+    host.insert_resource(ClientResource::new(host_end));
+    host.insert_resource(CurrentState(NetworkState::Host));
+    let mut client = headless_app();
+    client.insert_resource(ClientResource::new(client_end));
+    client.insert_resource(CurrentState(NetworkState::Client));
+
+    for app in [&mut host, &mut client] {
+        app.world.resource_mut::<GameSettings>().mode = mode;
+        app.insert_resource(NextState(GameState::Playing));
+    }
+    update(&mut host, &mut client);
+    (host, client)
+}
+
+/// Runs a few frames on both apps so messages get sent, received and acted on
+fn update(host: &mut App, client: &mut App) {
+    for _ in 0..5 {
+        host.update();
+        client.update();
+    }
+}
+
+fn fill_rows(app: &mut App, rows: std::ops::Range<usize>, skip_column: Option<usize>) {
+    let mut board = app.world.resource_mut::<OwnTetrisBoard>();
+    for x in 0..board.tiles.len() {
+        if Some(x) == skip_column {
+            continue;
+        }
+        for y in rows.clone() {
+            board.set(
+                IVec2::new(x as i32, y as i32),
+                Some(TetrisTile {
+                    kind: PieceKind::Garbage,
+                }),
+            );
+        }
+    }
+}
+
+#[test]
+fn boards_garbage_and_game_over_reach_the_other_player() {
+    let (mut host, mut client) = connected_apps(GameMode::Normal);
+    assert!(host.world.contains_resource::<CurrentPiece>());
+
+    // A placement shows up on the other player's copy of our board
+    host.world.send_event(TetrisMove::HardDrop);
+    update(&mut host, &mut client);
+    let own = host.world.resource::<OwnTetrisBoard>();
+    let other = client.world.resource::<OtherTetrisBoard>();
+    assert!(!own.is_empty());
+    assert_eq!(own.tiles, other.tiles);
+
+    // Clearing two lines sends a line of garbage
+    let height = host.world.resource::<OwnTetrisBoard>().height();
+    fill_rows(&mut host, height - 2..height, None);
+    host.world.send_event(TetrisMove::HardDrop);
+    update(&mut host, &mut client);
+    assert_eq!(host.world.resource::<GameStats>().lines, 2);
+    assert_eq!(**client.world.resource::<PendingGarbage>(), 1);
+
+    // With the whole board full the next piece can't spawn, the host tops
+    // out and the client wins the round
+    fill_rows(&mut host, 0..height, Some(0));
+    host.world.send_event(TetrisMove::HardDrop);
+    update(&mut host, &mut client);
+    let series = &client.world.resource::<Lobby>().series;
+    assert_eq!(series.client_wins, 1);
+    assert_eq!(series.last_winner, Some(Player::Client));
+    for app in [&host, &client] {
+        assert_eq!(
+            app.world.resource::<CurrentState<GameState>>().0,
+            GameState::Results
+        );
+    }
+}
+
+fn run_out_clock(app: &mut App, score: usize) {
+    let mut stats = app.world.resource_mut::<GameStats>();
+    stats.time = ULTRA_TIME;
+    stats.score = score;
+}
+
+#[test]
+fn ultra_waits_for_the_clients_final_score() {
+    let (mut host, mut client) = connected_apps(GameMode::Ultra);
+
+    // Ahead when our clock runs out, but the client still has time left
+    run_out_clock(&mut host, 100);
+    update(&mut host, &mut client);
+    assert_eq!(
+        host.world.resource::<CurrentState<GameState>>().0,
+        GameState::Playing
+    );
+
+    // The client's last placements put it ahead before its own clock ends
+    run_out_clock(&mut client, 200);
+    update(&mut host, &mut client);
+    let series = &client.world.resource::<Lobby>().series;
+    assert_eq!(series.client_wins, 1);
+    assert_eq!(series.last_winner, Some(Player::Client));
+    for app in [&host, &client] {
+        assert_eq!(
+            app.world.resource::<CurrentState<GameState>>().0,
+            GameState::Results
+        );
+    }
+}
@@ -1,46 +1,228 @@
+use std::time::Duration;
+
 use bevy::prelude::*;
-use rand::{seq::SliceRandom, thread_rng};
+use iyes_loopless::prelude::*;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
 use serde::{Deserialize, Serialize};
 
-// This post wwas a big help
-// https://stackoverflow.com/a/38596291
-
-pub const SHAPES: [TetrisPiece; 7] = [
-    // degrees          0       90      180     270
-    TetrisPiece::new([0x4640, 0x0E40, 0x4C40, 0x4E00]), // 'T'
-    TetrisPiece::new([0x8C40, 0x6C00, 0x8C40, 0x6C00]), // 'S'
-    TetrisPiece::new([0x4C80, 0xC600, 0x4C80, 0xC600]), // 'Z'
-    TetrisPiece::new([0x4444, 0x0F00, 0x4444, 0x0F00]), // 'I'
-    TetrisPiece::new([0x44C0, 0x8E00, 0xC880, 0xE200]), // 'J'
-    TetrisPiece::new([0x88C0, 0xE800, 0xC440, 0x2E00]), // 'L'
-    TetrisPiece::new([0xCC00, 0xCC00, 0xCC00, 0xCC00]), // 'O'
-];
-
-pub const COLORS: [Color; 6] = [
-    Color::hsl(0.0, 0.7, 0.8),
-    Color::hsl(50.0, 0.7, 0.8),
-    Color::hsl(100.0, 0.7, 0.8),
-    Color::hsl(175.0, 0.7, 0.8),
-    Color::hsl(240.0, 0.7, 0.8),
-    Color::hsl(300.0, 0.7, 0.8),
-];
+use crate::{
+    modes::coop_spawn_offset, movement::enter_board, network::NetworkState, pieces::PieceSet,
+    GameMode, GameSettings, TetrisMove,
+};
+
+/// Columns and visible rows of the usual board
+pub const STANDARD_BOARD_SIZE: (usize, usize) = (10, 20);
+/// Rows above the visible ones that pieces spawn in and the stack can be
+/// pushed up into, piece sets with bigger pieces get more
+pub const HIDDEN_ROWS: usize = 4;
+
+/// A T-spin that needed the last kick always counts as a full one
+pub const LAST_KICK: usize = 4;
+
+/// The offsets to try when rotating from the given rotation, already flipped
+/// to board coordinates
+pub fn wall_kicks(piece: &TetrisPiece, rotation: usize, clockwise: bool) -> Vec<IVec2> {
+    // Pieces without a table only rotate in place
+    if piece.kicks.is_empty() {
+        return vec![IVec2::ZERO];
+    }
+    // The guideline counts its states from the spawn orientation
+    let state = (rotation + 4 - piece.spawn_rotation % 4) % 4;
+    let row = if clockwise {
+        state * 2
+    } else {
+        (state * 2 + 7) % 8
+    };
+    piece.kicks[row]
+        .iter()
+        .map(|(x, y)| IVec2::new(*x, -*y))
+        .collect()
+}
 
 #[derive(Component)]
 pub struct FallingTile;
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+/// Sent when a new piece spawns on top of existing tiles
+pub struct ToppedOutEvent;
+
+/// Sent when our piece lands and becomes part of the board
+pub struct PiecePlacedEvent {
+    pub piece: CurrentPiece,
+    pub t_spin: TSpin,
+}
+
+/// Sent after every placement, even when no lines were cleared
+#[derive(Clone, Copy)]
+pub struct LinesClearedEvent {
+    pub lines: usize,
+    pub t_spin: TSpin,
+    /// Clears in a row before this one
+    pub combo: usize,
+    /// Continues a chain of tetrises and T-spins
+    pub back_to_back: bool,
+    /// Left the whole board empty
+    pub perfect_clear: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TSpin {
+    #[default]
+    None,
+    Mini,
+    Full,
+}
+
+/// The last thing that successfully moved the piece
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LastMove {
+    #[default]
+    Shift,
+    /// With the index of the wall kick that made the rotation fit
+    Rotation(usize),
+}
+
+/// What a tile came from, the theme decides how each looks
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PieceKind {
+    T,
+    S,
+    Z,
+    I,
+    J,
+    L,
+    O,
+    Garbage,
+    /// From a piece set file, numbered in the order of the set
+    Custom(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TetrisTile {
-    pub color: Color,
+    pub kind: PieceKind,
 }
 
-#[derive(Resource)]
+#[derive(Resource, Clone)]
 pub struct CurrentPiece {
     pub piece: TetrisPiece,
     pub position: IVec2,
     pub rotation: usize,
     pub tiles: Vec<(IVec2, TetrisTile)>,
+    pub last_move: LastMove,
+}
+
+impl CurrentPiece {
+    /// A new piece in its spawn orientation with its top on `spawn_row`,
+    /// centered in the first `width` columns and rounded to the left
+    pub fn new(piece: TetrisPiece, width: usize, spawn_row: i32) -> Self {
+        let rotation = piece.spawn_rotation;
+        let cells = piece.cells(rotation);
+        let (min, max) = piece.bounds(rotation);
+        let left = (width as i32 - (max.x - min.x + 1)) / 2;
+        let position = IVec2::new(left - min.x, spawn_row - min.y);
+
+        let kind = piece.kind;
+        CurrentPiece {
+            piece,
+            position,
+            rotation,
+            tiles: cells
+                .into_iter()
+                .map(|e| (e + position, TetrisTile { kind }))
+                .collect(),
+            last_move: LastMove::default(),
+        }
+    }
+    /// Moves the piece sideways without checking for anything in the way
+    pub fn shift(&mut self, columns: i32) {
+        self.position.x += columns;
+        for (pos, _) in self.tiles.iter_mut() {
+            pos.x += columns;
+        }
+    }
 }
 
+/// The other players falling piece, only used for drawing
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct OtherCurrentPiece(pub Vec<(IVec2, TetrisTile)>);
+
+/// Our own stats for the current game
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GameStats {
+    pub time: Duration,
+    pub lines: usize,
+    pub pieces: usize,
+    pub score: usize,
+    pub tetrises: usize,
+    pub t_spins: usize,
+    pub max_combo: usize,
+    pub perfect_clears: usize,
+}
+
+impl GameStats {
+    /// Share of the cleared lines that were cleared four at a time
+    pub fn tetris_rate(&self) -> f32 {
+        if self.lines > 0 {
+            (self.tetrises * 4) as f32 / self.lines as f32
+        } else {
+            0.0
+        }
+    }
+    pub fn pieces_per_second(&self) -> f32 {
+        let secs = self.time.as_secs_f32();
+        if secs > 0.0 {
+            self.pieces as f32 / secs
+        } else {
+            0.0
+        }
+    }
+    pub fn add_clear(&mut self, e: &LinesClearedEvent) {
+        self.lines += e.lines;
+        self.score += clear_points(e);
+        if e.lines == 4 {
+            self.tetrises += 1;
+        }
+        if e.t_spin != TSpin::None {
+            self.t_spins += 1;
+        }
+        if e.perfect_clear {
+            self.perfect_clears += 1;
+        }
+        self.max_combo = self.max_combo.max(e.combo);
+    }
+}
+
+/// Consecutive placements that cleared lines, and consecutive difficult
+/// clears which are tetrises and T-spins
+#[derive(Resource, Debug, Clone, Copy, Default)]
+pub struct ClearChain {
+    /// Clears in a row minus one, none once a piece clears nothing
+    pub combo: Option<usize>,
+    /// Difficult clears in a row minus one, none after any other clear
+    pub back_to_back: Option<usize>,
+}
+
+impl ClearChain {
+    /// Adds a placement to the chains, returns the combo and if it was back
+    /// to back. Placing without clearing only breaks the combo
+    pub fn update(&mut self, lines: usize, t_spin: TSpin) -> (usize, bool) {
+        if lines == 0 {
+            self.combo = None;
+            return (0, false);
+        }
+        let combo = self.combo.map_or(0, |e| e + 1);
+        self.combo = Some(combo);
+        self.back_to_back = match lines == 4 || t_spin != TSpin::None {
+            true => Some(self.back_to_back.map_or(0, |e| e + 1)),
+            false => None,
+        };
+        (combo, self.back_to_back.map_or(false, |e| e > 0))
+    }
+}
+
+/// The stats the other player last sent us
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct OtherGameStats(pub GameStats);
+
 #[derive(Resource, Deref, DerefMut)]
 pub struct OwnTetrisBoard(pub TetrisBoard);
 
@@ -50,16 +232,74 @@ pub struct OtherTetrisBoard(pub TetrisBoard);
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TetrisBoard {
     pub offset: Vec2,
-    pub tiles: [[Option<TetrisTile>; 20]; 10],
+    /// Every column from left to right, the first `hidden_rows` of each are
+    /// above the visible board
+    pub tiles: Vec<Vec<Option<TetrisTile>>>,
+    pub hidden_rows: usize,
+}
+
+/// The 3-corner rule, a T piece that rotated into place with three of the
+/// four corners around its center taken. It's a mini unless both corners it
+/// points towards are taken or it needed the last wall kick to get there.
+/// The walls and floor count as taken
+pub fn t_spin(piece: &CurrentPiece, board: &TetrisBoard) -> TSpin {
+    let kick = match piece.last_move {
+        LastMove::Rotation(kick) if piece.piece.kind == PieceKind::T => kick,
+        _ => return TSpin::None,
+    };
+    let center = piece.position + IVec2::ONE;
+    let taken = |x: i32, y: i32| !board.tile_empty(center + IVec2::new(x, y));
+
+    let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)]
+        .iter()
+        .filter(|(x, y)| taken(*x, *y))
+        .count();
+    if corners < 3 {
+        return TSpin::None;
+    }
+
+    // Pointing right, down, left and up
+    let front = match piece.rotation % 4 {
+        0 => [(1, -1), (1, 1)],
+        1 => [(-1, 1), (1, 1)],
+        2 => [(-1, -1), (-1, 1)],
+        _ => [(-1, -1), (1, -1)],
+    };
+    if front.iter().all(|(x, y)| taken(*x, *y)) || kick == LAST_KICK {
+        TSpin::Full
+    } else {
+        TSpin::Mini
+    }
 }
 
 impl TetrisBoard {
-    pub const fn new(offset: Vec2) -> Self {
+    /// A board with `width` columns and `rows` visible rows
+    pub fn new(offset: Vec2, size: (usize, usize)) -> Self {
+        Self::with_hidden_rows(offset, size, HIDDEN_ROWS)
+    }
+    fn with_hidden_rows(offset: Vec2, (width, rows): (usize, usize), hidden_rows: usize) -> Self {
         Self {
             offset,
-            tiles: [[None; 20]; 10],
+            tiles: vec![vec![None; rows + hidden_rows]; width],
+            hidden_rows,
+        }
+    }
+    /// The board each player gets with these settings
+    pub fn for_settings(offset: Vec2, settings: &GameSettings) -> Self {
+        let (width, rows) = settings.board_size;
+        let hidden_rows = settings.piece_set.hidden_rows();
+        match settings.mode {
+            // Puzzles are made for the usual board and pieces
+            GameMode::Puzzle => Self::new(offset, STANDARD_BOARD_SIZE),
+            // Both players share one board twice as wide
+            GameMode::Coop => Self::with_hidden_rows(offset, (width * 2, rows), hidden_rows),
+            _ => Self::with_hidden_rows(offset, (width, rows), hidden_rows),
         }
     }
+    /// Rows including the hidden ones
+    pub fn height(&self) -> usize {
+        self.tiles.first().map_or(0, |col| col.len())
+    }
     pub fn set(&mut self, tile: IVec2, value: Option<TetrisTile>) {
         if let Some(e) = self.tiles.get_mut(tile.x as usize) {
             if let Some(e) = e.get_mut(tile.y as usize) {
@@ -67,14 +307,21 @@ impl TetrisBoard {
             }
         }
     }
+    /// Where a tile is drawn, with the visible rows centered on the offset
     pub fn get_position(&self, tile: IVec2) -> Vec3 {
+        let row = tile.y - self.hidden_rows as i32;
         [
-            (tile.x as f32 * 8.0) - (5.0 * 8.0) + 4.0 + self.offset.x,
-            -(tile.y as f32 * 8.0) + (10.0 * 8.0) - 4.0 + self.offset.y,
+            (tile.x as f32 * 8.0) - (self.tiles.len() as f32 / 2.0 * 8.0) + 4.0 + self.offset.x,
+            -(row as f32 * 8.0) + ((self.height() - self.hidden_rows) as f32 / 2.0 * 8.0) - 4.0
+                + self.offset.y,
             0.0,
         ]
         .into()
     }
+    /// If the tile is below the hidden rows
+    pub fn is_visible(&self, tile: IVec2) -> bool {
+        tile.y >= self.hidden_rows as i32
+    }
     pub fn tile_empty(&self, tile: IVec2) -> bool {
         if let Some(e) = self.tiles.get(tile.x as usize) {
             if let Some(e) = e.get(tile.y as usize) {
@@ -83,27 +330,133 @@ impl TetrisBoard {
         }
         false
     }
+    pub fn is_empty(&self) -> bool {
+        self.tiles.iter().flatten().all(|e| e.is_none())
+    }
+    pub fn clear(&mut self) {
+        for col in self.tiles.iter_mut() {
+            col.fill(None);
+        }
+    }
+    pub fn place(&mut self, piece: &CurrentPiece) {
+        for (pos, tile) in piece.tiles.iter() {
+            self.set(*pos, Some(*tile));
+        }
+    }
+    /// Removes full lines and moves everything above them down,
+    /// returns the number of lines cleared
+    pub fn clear_lines(&mut self) -> usize {
+        let mut is_line = vec![true; self.height()];
+        for col in &self.tiles {
+            for (j, tile) in col.iter().enumerate() {
+                if tile.is_none() {
+                    is_line[j] = false;
+                }
+            }
+        }
+
+        for i in is_line
+            .iter()
+            .enumerate()
+            .filter_map(|(i, l)| if *l { Some(i) } else { None })
+        {
+            for col in 0..self.tiles.len() {
+                self.tiles[col][i] = None;
+                for row in (0..i).rev() {
+                    let t = self.tiles[col][row];
+                    self.tiles[col][row] = None;
+                    self.tiles[col][row + 1] = t;
+                }
+            }
+        }
+
+        is_line.iter().filter(|l| **l).count()
+    }
+    /// Pushes everything up and fills the bottom rows with garbage that has a
+    /// hole in the same column, returns false if tiles got pushed off the top
+    pub fn add_garbage(&mut self, rows: usize, hole: usize) -> bool {
+        let mut fits = true;
+        for (x, col) in self.tiles.iter_mut().enumerate() {
+            let height = col.len();
+            let rows = rows.min(height);
+            fits &= col[..rows].iter().all(|e| e.is_none());
+            col.rotate_left(rows);
+            for tile in col[height - rows..].iter_mut() {
+                *tile = match x == hole {
+                    true => None,
+                    false => Some(TetrisTile {
+                        kind: PieceKind::Garbage,
+                    }),
+                };
+            }
+        }
+        fits
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct TetrisPiece {
-    pub data: [u16; 4],
+    pub kind: PieceKind,
+    pub spawn_rotation: usize,
+    /// Width and height of the grid the rotations are drawn in
+    pub size: usize,
+    /// A bit for each cell of the grid per rotation, row by row from the top
+    /// left which is the highest bit
+    pub data: [u32; 4],
+    /// Wall kicks in the row order of the SRS tables with y pointing up,
+    /// empty if the piece only rotates in place
+    pub kicks: Vec<Vec<(i32, i32)>>,
 }
 
 impl TetrisPiece {
-    pub const fn new(data: [u16; 4]) -> Self {
-        Self { data }
+    pub fn new(
+        kind: PieceKind,
+        spawn_rotation: usize,
+        size: usize,
+        data: [u32; 4],
+        kicks: Vec<Vec<(i32, i32)>>,
+    ) -> Self {
+        Self {
+            kind,
+            spawn_rotation,
+            size,
+            data,
+            kicks,
+        }
+    }
+    /// The bit of a cell in a grid this size
+    pub const fn bit(size: usize, x: usize, y: usize) -> u32 {
+        1 << (size * size - 1 - (y * size + x))
     }
-    pub const fn value(&self, rotation: usize, x: u8, y: u8) -> bool {
-        self.data[rotation % 4] & (0x8000 >> (y * 4 + x)) != 0
+    pub fn value(&self, rotation: usize, x: usize, y: usize) -> bool {
+        self.data[rotation % 4] & Self::bit(self.size, x, y) != 0
+    }
+    /// The filled cells of a rotation from the top left of its grid
+    pub fn cells(&self, rotation: usize) -> Vec<IVec2> {
+        let mut cells = vec![];
+        for x in 0..self.size {
+            for y in 0..self.size {
+                if self.value(rotation, x, y) {
+                    cells.push(IVec2::new(x as i32, y as i32));
+                }
+            }
+        }
+        cells
+    }
+    /// The smallest and largest cell of a rotation
+    pub fn bounds(&self, rotation: usize) -> (IVec2, IVec2) {
+        let cells = self.cells(rotation);
+        let min = cells.iter().fold(IVec2::splat(i32::MAX), |a, b| a.min(*b));
+        let max = cells.iter().fold(IVec2::splat(i32::MIN), |a, b| a.max(*b));
+        (min, max)
     }
 }
 
 impl std::fmt::Display for TetrisPiece {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for r in 0..4 {
-            for y in 0..4 {
-                for x in 0..4 {
+            for y in 0..self.size {
+                for x in 0..self.size {
                     write!(f, "{}, ", self.value(r, x, y) as u8)?;
                 }
                 writeln!(f)?;
@@ -114,88 +467,152 @@ impl std::fmt::Display for TetrisPiece {
     }
 }
 
-#[derive(Resource)]
+/// Seeded so both players, and replays, get the same pieces
+#[derive(Resource, Clone)]
 pub struct TetrisPieceBuffer {
+    set: PieceSet,
     pieces: Vec<TetrisPiece>,
+    rng: StdRng,
 }
 
 impl TetrisPieceBuffer {
-    pub fn new() -> Self {
-        let mut rng = thread_rng();
-        let mut pieces = SHAPES.to_vec();
-        pieces.shuffle(&mut rng);
-        Self { pieces }
+    pub fn new(seed: u64, set: PieceSet) -> Self {
+        Self::with_sequence(seed, set, vec![])
+    }
+    /// Gives the pieces in order before going back to bags from the set
+    pub fn with_sequence(seed: u64, set: PieceSet, mut sequence: Vec<TetrisPiece>) -> Self {
+        sequence.reverse();
+        Self {
+            set,
+            pieces: sequence,
+            rng: StdRng::seed_from_u64(seed),
+        }
     }
     pub fn pop(&mut self) -> TetrisPiece {
         if self.pieces.is_empty() {
-            let mut rng = thread_rng();
-            let mut pieces = SHAPES.to_vec();
-            pieces.shuffle(&mut rng);
+            let mut pieces: Vec<TetrisPiece> = self
+                .set
+                .bag
+                .iter()
+                .map(|e| self.set.pieces[*e].clone())
+                .collect();
+            pieces.shuffle(&mut self.rng);
             self.pieces = pieces;
         }
         self.pieces.pop().unwrap()
     }
+    /// The next piece ready to spawn on a board this wide
+    pub fn next_piece(&mut self, width: usize) -> CurrentPiece {
+        let spawn_row = self.set.spawn_row();
+        CurrentPiece::new(self.pop(), width, spawn_row)
+    }
 }
 
-pub fn spawn_piece(mut commands: Commands, mut buf: ResMut<TetrisPieceBuffer>) {
-    let mut rng = thread_rng();
-    let color = COLORS.choose(&mut rng).unwrap();
-    let piece = buf.pop();
-
-    let mut current_piece = CurrentPiece {
-        piece: piece.clone(),
-        position: [3, 0].into(),
-        rotation: 0,
-        tiles: vec![],
+pub fn spawn_piece(
+    mut commands: Commands,
+    mut buf: ResMut<TetrisPieceBuffer>,
+    mut board: ResMut<OwnTetrisBoard>,
+    settings: Res<GameSettings>,
+    network_state: Res<CurrentState<NetworkState>>,
+    mut topped_out: EventWriter<ToppedOutEvent>,
+) {
+    // In co-op each player spawns in the middle of their own half
+    let mut current_piece = if settings.mode == GameMode::Coop {
+        let mut piece = buf.next_piece(board.tiles.len() / 2);
+        piece.shift(coop_spawn_offset(network_state.0, board.tiles.len()));
+        piece
+    } else {
+        buf.next_piece(board.tiles.len())
     };
 
-    for x in 0..4 {
-        for y in 0..4 {
-            if piece.value(0, x, y) {
-                let board_position = [x as i32 + 3, y as i32].into();
-                current_piece.tiles.push((
-                    board_position,
-                    TetrisTile {
-                        color: color.to_owned(),
-                    },
-                ));
-            }
+    if !enter_board(&mut current_piece, &board) {
+        // Zen never ends, the board is cleared to make room instead
+        if settings.mode == GameMode::Zen {
+            board.clear();
+            enter_board(&mut current_piece, &board);
+        } else {
+            topped_out.send(ToppedOutEvent);
         }
     }
 
     commands.insert_resource(current_piece);
 }
 
-pub fn clear_lines(mut board: ResMut<OwnTetrisBoard>) {
-    let mut is_line = [true; 20];
-    for col in board.tiles {
-        for (j, tile) in col.iter().enumerate() {
-            if tile.is_none() {
-                is_line[j] = false;
-            }
-        }
+pub fn clear_lines(
+    mut board: ResMut<OwnTetrisBoard>,
+    mut stats: ResMut<GameStats>,
+    mut chain: ResMut<ClearChain>,
+    mut placed: EventReader<PiecePlacedEvent>,
+    mut cleared: EventWriter<LinesClearedEvent>,
+) {
+    let t_spin = placed.iter().last().map_or(TSpin::None, |e| e.t_spin);
+    let event = clear_board(&mut board, &mut chain, t_spin);
+    stats.add_clear(&event);
+    cleared.send(event);
+}
+
+/// Clears the lines of a placement and updates the chains
+pub fn clear_board(
+    board: &mut TetrisBoard,
+    chain: &mut ClearChain,
+    t_spin: TSpin,
+) -> LinesClearedEvent {
+    let lines = board.clear_lines();
+    let (combo, back_to_back) = chain.update(lines, t_spin);
+    LinesClearedEvent {
+        lines,
+        t_spin,
+        combo,
+        back_to_back,
+        perfect_clear: lines > 0 && board.is_empty(),
     }
+}
 
-    let _points = match is_line.len() {
-        1 => 40,
-        2 => 100,
-        3 => 30,
-        4 => 1200,
-        _ => 0,
-    };
+/// A point for every row soft dropped and two for every row hard dropped
+pub fn drop_points(m: TetrisMove, rows: usize) -> usize {
+    match m {
+        TetrisMove::HardDrop => rows * 2,
+        _ => rows,
+    }
+}
 
-    for i in is_line
-        .iter()
-        .enumerate()
-        .filter_map(|(i, l)| if *l { Some(i) } else { None })
-    {
-        for col in 0..board.tiles.len() {
-            board.tiles[col][i] = None;
-            for row in (0..i).rev() {
-                let t = board.tiles[col][row];
-                board.tiles[col][row] = None;
-                board.tiles[col][row + 1] = t;
-            }
-        }
+/// T-spins score like the guideline, everything else like the original game.
+/// Back to back clears are worth half again, every combo adds 50 and perfect
+/// clears get the guideline bonus on top
+pub fn clear_points(e: &LinesClearedEvent) -> usize {
+    let mut points = line_clear_points(e.lines, e.t_spin);
+    if e.back_to_back {
+        points = points * 3 / 2;
+    }
+    if e.perfect_clear {
+        points += match e.lines {
+            1 => 800,
+            2 => 1200,
+            3 => 1800,
+            _ if e.back_to_back => 3200,
+            _ => 2000,
+        };
     }
+    points + e.combo * 50
+}
+
+fn line_clear_points(lines: usize, t_spin: TSpin) -> usize {
+    match (t_spin, lines) {
+        (TSpin::Mini, 0) => 100,
+        (TSpin::Mini, 1) => 200,
+        (TSpin::Mini, _) => 400,
+        (TSpin::Full, 0) => 400,
+        (TSpin::Full, 1) => 800,
+        (TSpin::Full, 2) => 1200,
+        (TSpin::Full, _) => 1600,
+        (TSpin::None, 1) => 40,
+        (TSpin::None, 2) => 100,
+        (TSpin::None, 3) => 300,
+        (TSpin::None, 4) => 1200,
+        _ => 0,
+    }
+}
+
+pub fn update_game_time(time: Res<Time>, mut stats: ResMut<GameStats>) {
+    stats.time += time.delta();
 }
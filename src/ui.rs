@@ -1,4 +1,18 @@
-use crate::{GameState, network::{HostAddress, NetworkState}};
+use crate::{
+    bot::BotPlayer,
+    chat::{ChatHistory, ChatInput, BACKSPACE_CHAR},
+    lobby::{Countdown, Lobby, LobbyAction},
+    modes::{
+        ultra_time_left, CheeseProgress, MarathonProgress, PuzzleGoal, PuzzleProgress,
+        SprintProgress, ZenState, MARATHON_LEVELS, SPLIT_LINES, SPRINT_LINES,
+    },
+    movement::{Handling, ARR_OPTIONS, DAS_OPTIONS, SOFT_DROP_FACTORS},
+    network::{ClientResource, HostAddress, NetworkState},
+    replay::{Replay, ReplayViewer},
+    series::Player,
+    tetris::{ClearChain, GameStats, LinesClearedEvent, OtherGameStats, TSpin, TetrisBoard},
+    GameMode, GameSettings, GameState,
+};
 use bevy::prelude::*;
 use iyes_loopless::prelude::*;
 
@@ -14,9 +28,17 @@ impl Plugin for UiPlugin {
 
         app.add_enter_system(GameState::Menu, setup_menu);
         app.add_enter_system(GameState::JoinMenu, setup_join_menu);
+        app.add_enter_system(GameState::Lobby, setup_lobby_menu);
+        app.add_enter_system(GameState::Results, setup_results_menu);
+        app.add_enter_system(GameState::Replay, setup_replay_ui);
+        app.add_enter_system(GameState::Playing, setup_hud);
 
         app.add_exit_system(GameState::Menu, despawn_ui);
         app.add_exit_system(GameState::JoinMenu, despawn_ui);
+        app.add_exit_system(GameState::Lobby, despawn_ui);
+        app.add_exit_system(GameState::Results, despawn_ui);
+        app.add_exit_system(GameState::Replay, despawn_ui);
+        app.add_exit_system(GameState::Playing, despawn_ui);
 
         app.add_system_set(
             ConditionSet::new()
@@ -31,6 +53,45 @@ impl Plugin for UiPlugin {
                 .with_system(ip_input_system)
                 .into(),
         );
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Lobby)
+                .with_system(update_lobby_ui)
+                .into(),
+        );
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Results)
+                .with_system(update_results_ui)
+                .into(),
+        );
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .with_system(update_hud)
+                .with_system(update_callout)
+                .with_system(update_chain_counter.run_if_resource_exists::<ClearChain>())
+                .into(),
+        );
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Replay)
+                .run_if_resource_exists::<ReplayViewer>()
+                .with_system(update_replay_ui)
+                .into(),
+        );
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_if_resource_exists::<ClientResource>()
+                .with_system(spawn_chat_ui)
+                .with_system(update_chat_ui)
+                .into(),
+        );
     }
 }
 
@@ -47,11 +108,95 @@ enum MenuButton {
     Host,
     Join,
     JoinGo,
+    Ready,
+    Mode,
+    Gravity,
+    BestOf,
+    StartLevel,
+    Endless,
+    CheeseRows,
+    Puzzle,
+    BoardSize,
+    PieceSet,
+    Rematch,
+    Replay,
+    Bot,
+    BotDifficulty,
+    BotSpeed,
+    SoftDrop,
+    Das,
+    Arr,
+    Solo,
+    Menu,
 }
 
 #[derive(Component)]
 struct IpInputText;
 
+#[derive(Component)]
+enum LobbyText {
+    Players,
+    Mode,
+    Gravity,
+    BestOf,
+    StartLevel,
+    Endless,
+    CheeseRows,
+    Puzzle,
+    BoardSize,
+    PieceSet,
+    BotDifficulty,
+    BotSpeed,
+    SoftDrop,
+    Das,
+    Arr,
+    Ready,
+    Menu,
+    Countdown,
+}
+
+#[derive(Component)]
+struct ReplayText;
+
+#[derive(Component)]
+struct ChatRoot;
+
+#[derive(Component)]
+enum ChatText {
+    History,
+    Input,
+}
+
+#[derive(Component)]
+enum ResultsText {
+    Round,
+    Series,
+    Stats,
+    Rematch,
+}
+
+#[derive(Component)]
+struct HudText;
+
+/// Big text for special clears that fades out
+#[derive(Component)]
+struct CalloutText {
+    kind: Callout,
+    timer: Timer,
+}
+
+#[derive(Clone, Copy)]
+enum Callout {
+    Clear,
+    PerfectClear,
+}
+
+/// Combo and back to back counters that pop when they change
+#[derive(Component)]
+struct ChainText(Timer);
+
+const CHAIN_FONT_SIZE: f32 = 25.0;
+
 fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands.insert_resource(UiAssets {
         font: asset_server.load("roboto.ttf"),
@@ -70,57 +215,39 @@ fn setup_menu(mut commands: Commands, ui_assets: Res<UiAssets>) {
             ..default()
         })
         .with_children(|parent| {
-            parent
-                .spawn((
-                    ButtonBundle {
-                        style: Style {
-                            size: Size::new(Val::Px(150.0), Val::Px(65.0)),
-                            justify_content: JustifyContent::Center,
-                            align_items: AlignItems::Center,
-                            margin: UiRect::horizontal(Val::Px(10.0)),
+            for (button, text) in [
+                (MenuButton::Solo, "Solo"),
+                (MenuButton::Host, "Host"),
+                (MenuButton::Join, "Join"),
+                (MenuButton::Bot, "Bot"),
+                (MenuButton::Replay, "Replay"),
+            ] {
+                parent
+                    .spawn((
+                        ButtonBundle {
+                            style: Style {
+                                size: Size::new(Val::Px(150.0), Val::Px(65.0)),
+                                justify_content: JustifyContent::Center,
+                                align_items: AlignItems::Center,
+                                margin: UiRect::horizontal(Val::Px(10.0)),
+                                ..default()
+                            },
+                            background_color: NORMAL_BUTTON.into(),
                             ..default()
                         },
-                        background_color: NORMAL_BUTTON.into(),
-                        ..default()
-                    },
-                    MenuButton::Host,
-                ))
-                .with_children(|parent| {
-                    parent.spawn(TextBundle::from_section(
-                        "Host",
-                        TextStyle {
-                            font: ui_assets.font.clone(),
-                            font_size: 40.0,
-                            color: Color::rgb(0.9, 0.9, 0.9),
-                        },
-                    ));
-                });
-
-            parent
-                .spawn((
-                    ButtonBundle {
-                        style: Style {
-                            size: Size::new(Val::Px(150.0), Val::Px(65.0)),
-                            justify_content: JustifyContent::Center,
-                            align_items: AlignItems::Center,
-                            margin: UiRect::horizontal(Val::Px(10.0)),
-                            ..default()
-                        },
-                        background_color: NORMAL_BUTTON.into(),
-                        ..default()
-                    },
-                    MenuButton::Join,
-                ))
-                .with_children(|parent| {
-                    parent.spawn(TextBundle::from_section(
-                        "Join",
-                        TextStyle {
-                            font: ui_assets.font.clone(),
-                            font_size: 40.0,
-                            color: Color::rgb(0.9, 0.9, 0.9),
-                        },
-                    ));
-                });
+                        button,
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle::from_section(
+                            text,
+                            TextStyle {
+                                font: ui_assets.font.clone(),
+                                font_size: 40.0,
+                                color: Color::rgb(0.9, 0.9, 0.9),
+                            },
+                        ));
+                    });
+            }
         });
 }
 
@@ -175,6 +302,782 @@ fn setup_join_menu(mut commands: Commands, ui_assets: Res<UiAssets>) {
         });
 }
 
+fn setup_lobby_menu(
+    mut commands: Commands,
+    ui_assets: Res<UiAssets>,
+    bot: Option<Res<BotPlayer>>,
+    client: Option<Res<ClientResource>>,
+    network_state: Res<CurrentState<NetworkState>>,
+) {
+    let solo = network_state.0 == NetworkState::None && client.is_none();
+    let text_style = TextStyle {
+        font: ui_assets.font.clone(),
+        font_size: 30.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                LobbyText::Players,
+            ));
+
+            let mut buttons = vec![
+                (MenuButton::Mode, LobbyText::Mode),
+                (MenuButton::Gravity, LobbyText::Gravity),
+                (MenuButton::StartLevel, LobbyText::StartLevel),
+                (MenuButton::Endless, LobbyText::Endless),
+                (MenuButton::CheeseRows, LobbyText::CheeseRows),
+                (MenuButton::Puzzle, LobbyText::Puzzle),
+                (MenuButton::BoardSize, LobbyText::BoardSize),
+                (MenuButton::PieceSet, LobbyText::PieceSet),
+            ];
+            if !solo {
+                buttons.push((MenuButton::BestOf, LobbyText::BestOf));
+            }
+            if bot.is_some() {
+                buttons.push((MenuButton::BotDifficulty, LobbyText::BotDifficulty));
+                buttons.push((MenuButton::BotSpeed, LobbyText::BotSpeed));
+            }
+            buttons.push((MenuButton::Ready, LobbyText::Ready));
+            buttons.push((MenuButton::Menu, LobbyText::Menu));
+
+            for (button, text) in buttons {
+                spawn_lobby_button(parent, button, text, &text_style);
+            }
+
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 80.0,
+                        ..text_style.clone()
+                    },
+                ),
+                LobbyText::Countdown,
+            ));
+        });
+
+    // Handling is up to each player so it sits apart from the match options
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            for (button, text) in [
+                (MenuButton::SoftDrop, LobbyText::SoftDrop),
+                (MenuButton::Das, LobbyText::Das),
+                (MenuButton::Arr, LobbyText::Arr),
+            ] {
+                spawn_lobby_button(parent, button, text, &text_style);
+            }
+        });
+}
+
+fn spawn_lobby_button(
+    parent: &mut ChildBuilder,
+    button: MenuButton,
+    text: LobbyText,
+    text_style: &TextStyle,
+) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    size: Size::new(Val::Px(300.0), Val::Px(65.0)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    margin: UiRect::all(Val::Px(10.0)),
+                    ..default()
+                },
+                background_color: NORMAL_BUTTON.into(),
+                ..default()
+            },
+            button,
+        ))
+        .with_children(|parent| {
+            parent.spawn((TextBundle::from_section("", text_style.clone()), text));
+        });
+}
+
+fn update_lobby_ui(
+    lobby: Res<Lobby>,
+    settings: Res<GameSettings>,
+    bot: Option<Res<BotPlayer>>,
+    handling: Res<Handling>,
+    countdown: Option<Res<Countdown>>,
+    network_state: Res<CurrentState<NetworkState>>,
+    client: Option<Res<ClientResource>>,
+    mut query: Query<(&mut Text, &LobbyText)>,
+    mut buttons: Query<(&mut Style, &MenuButton)>,
+) {
+    let solo = network_state.0 == NetworkState::None && client.is_none();
+
+    // Only show the options of the picked mode
+    for (mut style, button) in &mut buttons {
+        let shown = match button {
+            MenuButton::StartLevel | MenuButton::Endless => settings.mode == GameMode::Marathon,
+            MenuButton::CheeseRows => settings.mode == GameMode::Cheese,
+            MenuButton::Puzzle => settings.mode == GameMode::Puzzle,
+            // Puzzles are always played on the usual board with the usual pieces
+            MenuButton::BoardSize | MenuButton::PieceSet => settings.mode != GameMode::Puzzle,
+            _ => continue,
+        };
+        let display = match shown {
+            true => Display::Flex,
+            false => Display::None,
+        };
+        if style.display != display {
+            style.display = display;
+        }
+    }
+
+    let ready = |ready: bool| if ready { "Ready" } else { "Not ready" };
+    let own_ready = match network_state.0 {
+        NetworkState::Client => lobby.client_ready,
+        _ => lobby.host_ready,
+    };
+
+    for (mut text, kind) in &mut query {
+        text.sections[0].value = match kind {
+            LobbyText::Players if solo => "Solo".to_string(),
+            LobbyText::Players if !lobby.connected => "Waiting for opponent...".to_string(),
+            LobbyText::Players => format!(
+                "Host: {}    Client: {}",
+                ready(lobby.host_ready),
+                ready(lobby.client_ready)
+            ),
+            LobbyText::Mode => format!("Mode: {:?}", settings.mode),
+            LobbyText::Gravity => format!("Gravity: {}ms", settings.gravity.as_millis()),
+            LobbyText::BestOf => format!("Best of {}", settings.best_of),
+            LobbyText::StartLevel => format!("Start level: {}", settings.start_level),
+            LobbyText::Endless if settings.endless => "Endless".to_string(),
+            LobbyText::Endless => format!("To level {MARATHON_LEVELS}"),
+            LobbyText::CheeseRows => format!("Garbage rows: {}", settings.cheese_rows),
+            LobbyText::BoardSize => {
+                // The size that gets played, co-op partners share a wider one
+                let board = TetrisBoard::for_settings(Vec2::ZERO, &settings);
+                let rows = board.height() - board.hidden_rows;
+                format!("Board: {}x{rows}", board.tiles.len())
+            }
+            LobbyText::PieceSet => format!("Pieces: {}", settings.piece_set.name),
+            LobbyText::Puzzle => match &settings.puzzle {
+                Some(puzzle) => format!("Puzzle: {}", puzzle.name),
+                None => "No puzzle".to_string(),
+            },
+            LobbyText::BotDifficulty => match &bot {
+                Some(bot) => format!("Bot: {:?}", bot.difficulty),
+                None => String::new(),
+            },
+            LobbyText::BotSpeed => match &bot {
+                Some(bot) => format!("Bot speed: {:?}", bot.speed),
+                None => String::new(),
+            },
+            LobbyText::SoftDrop => format!("Soft drop: {}x", handling.soft_drop_factor),
+            LobbyText::Das => format!("DAS: {}ms", handling.das.as_millis()),
+            LobbyText::Arr if handling.arr.is_zero() => "ARR: instant".to_string(),
+            LobbyText::Arr => format!("ARR: {}ms", handling.arr.as_millis()),
+            LobbyText::Ready if own_ready => "Unready".to_string(),
+            LobbyText::Ready => "Ready".to_string(),
+            LobbyText::Menu => "Menu".to_string(),
+            LobbyText::Countdown => countdown
+                .as_ref()
+                .map_or(String::new(), |e| format!("{}", e.remaining_secs().ceil())),
+        };
+    }
+}
+
+fn setup_results_menu(
+    mut commands: Commands,
+    ui_assets: Res<UiAssets>,
+    network_state: Res<CurrentState<NetworkState>>,
+) {
+    let text_style = TextStyle {
+        font: ui_assets.font.clone(),
+        font_size: 40.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 60.0,
+                        ..text_style.clone()
+                    },
+                ),
+                ResultsText::Round,
+            ));
+            parent.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                ResultsText::Series,
+            ));
+            parent.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                ResultsText::Stats,
+            ));
+
+            let mut buttons = vec![(MenuButton::Rematch, Some(ResultsText::Rematch))];
+            // Only offline can we leave without breaking the connection
+            if network_state.0 == NetworkState::None {
+                buttons.push((MenuButton::Menu, None));
+            }
+
+            for (button, text) in buttons {
+                parent
+                    .spawn((
+                        ButtonBundle {
+                            style: Style {
+                                size: Size::new(Val::Px(300.0), Val::Px(65.0)),
+                                justify_content: JustifyContent::Center,
+                                align_items: AlignItems::Center,
+                                margin: UiRect::all(Val::Px(10.0)),
+                                ..default()
+                            },
+                            background_color: NORMAL_BUTTON.into(),
+                            ..default()
+                        },
+                        button,
+                    ))
+                    .with_children(|parent| match text {
+                        Some(text) => {
+                            parent.spawn((TextBundle::from_section("", text_style.clone()), text));
+                        }
+                        None => {
+                            parent.spawn(TextBundle::from_section("Menu", text_style.clone()));
+                        }
+                    });
+            }
+        });
+}
+
+fn update_results_ui(
+    lobby: Res<Lobby>,
+    settings: Res<GameSettings>,
+    stats: Res<GameStats>,
+    other_stats: Option<Res<OtherGameStats>>,
+    sprint: Option<Res<SprintProgress>>,
+    marathon: Option<Res<MarathonProgress>>,
+    cheese: Option<Res<CheeseProgress>>,
+    puzzle: Option<Res<PuzzleProgress>>,
+    network_state: Res<CurrentState<NetworkState>>,
+    client: Option<Res<ClientResource>>,
+    mut query: Query<(&mut Text, &ResultsText)>,
+) {
+    let solo = client.is_none();
+    let ultra = settings.mode == GameMode::Ultra;
+    let coop = settings.mode == GameMode::Coop;
+    let own = match network_state.0 {
+        NetworkState::Client => Player::Client,
+        _ => Player::Host,
+    };
+    let series = &lobby.series;
+    let series_winner = series.winner(settings.best_of);
+
+    for (mut text, kind) in &mut query {
+        text.sections[0].value = match kind {
+            ResultsText::Round if solo && sprint.as_ref().map_or(false, |e| e.finished) => {
+                "Finished!".to_string()
+            }
+            ResultsText::Round if solo && ultra && ultra_time_left(&stats).is_zero() => {
+                "Time's up!".to_string()
+            }
+            ResultsText::Round if solo && marathon.as_ref().map_or(false, |e| e.finished) => {
+                "Marathon complete!".to_string()
+            }
+            ResultsText::Round if solo && cheese.as_ref().map_or(false, |e| e.finished) => {
+                "Dug through!".to_string()
+            }
+            ResultsText::Round if solo && puzzle.is_some() => {
+                match puzzle.as_ref().and_then(|e| e.passed) {
+                    Some(true) => "Puzzle solved!".to_string(),
+                    _ => "Puzzle failed".to_string(),
+                }
+            }
+            ResultsText::Round if settings.mode == GameMode::Zen => "Session over".to_string(),
+            ResultsText::Round if solo || coop => "Game over".to_string(),
+            ResultsText::Series if solo || coop => String::new(),
+            ResultsText::Stats if sprint.as_ref().map_or(false, |e| e.finished) => {
+                let sprint = sprint.as_ref().unwrap();
+                let splits = sprint
+                    .splits
+                    .iter()
+                    .map(|e| format!("{:.1}s", e.as_secs_f32()))
+                    .collect::<Vec<_>>()
+                    .join("  ");
+                let best = match sprint.personal_best {
+                    _ if sprint.new_best => "New personal best!".to_string(),
+                    Some(pb) => format!("Personal best: {:.3}s", pb.as_secs_f32()),
+                    None => String::new(),
+                };
+                format!(
+                    "Time: {:.3}s    PPS: {:.2}\nSplits: {splits}\n{best}",
+                    stats.time.as_secs_f32(),
+                    stats.pieces_per_second()
+                )
+            }
+            ResultsText::Stats if marathon.is_some() => format!(
+                "Level: {}    Lines: {}    Score: {}\nTime: {:.1}s    Tetris rate: {:.0}%",
+                marathon.as_ref().unwrap().level,
+                stats.lines,
+                stats.score,
+                stats.time.as_secs_f32(),
+                stats.tetris_rate() * 100.0
+            ),
+            ResultsText::Stats if cheese.as_ref().map_or(false, |e| e.finished) => format!(
+                "Dug through {} rows in {:.3}s    Pieces: {}",
+                settings.cheese_rows,
+                stats.time.as_secs_f32(),
+                stats.pieces
+            ),
+            ResultsText::Stats if coop => format!(
+                "Lines together: {}    Score together: {}",
+                stats.lines + other_stats.as_ref().map_or(0, |e| e.lines),
+                stats.score + other_stats.as_ref().map_or(0, |e| e.score)
+            ),
+            ResultsText::Stats if ultra && !solo => format!(
+                "Score: {}    Opponent: {}",
+                stats.score,
+                other_stats.as_ref().map_or(0, |e| e.score)
+            ),
+            ResultsText::Stats => format!(
+                "Lines: {}    Score: {}    Time: {:.1}s\nT-spins: {}    Max combo: {}    Perfect clears: {}",
+                stats.lines,
+                stats.score,
+                stats.time.as_secs_f32(),
+                stats.t_spins,
+                stats.max_combo,
+                stats.perfect_clears
+            ),
+            ResultsText::Rematch if solo || coop => "Play again".to_string(),
+            ResultsText::Round => match (series_winner, series.last_winner) {
+                (Some(winner), _) if winner == own => "You won the series!".to_string(),
+                (Some(_), _) => "You lost the series".to_string(),
+                (None, Some(winner)) if winner == own => "You won the round!".to_string(),
+                (None, Some(_)) => "You lost the round".to_string(),
+                (None, None) => "Waiting for result...".to_string(),
+            },
+            ResultsText::Series => {
+                let (own_wins, other_wins) = match own {
+                    Player::Host => (series.host_wins, series.client_wins),
+                    Player::Client => (series.client_wins, series.host_wins),
+                };
+                format!(
+                    "You {own_wins} - {other_wins} Opponent    (best of {})",
+                    settings.best_of
+                )
+            }
+            ResultsText::Rematch if series_winner.is_some() => "New series".to_string(),
+            ResultsText::Rematch => "Next round".to_string(),
+        };
+    }
+}
+
+fn setup_hud(mut commands: Commands, ui_assets: Res<UiAssets>) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..default()
+                },
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: ui_assets.font.clone(),
+                        font_size: 25.0,
+                        color: Color::rgb(0.9, 0.9, 0.9),
+                    },
+                ),
+                HudText,
+            ));
+        });
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(40.0),
+                    ..default()
+                },
+                size: Size::new(Val::Percent(100.0), Val::Auto),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            let callouts = [
+                (Callout::Clear, 40.0, Color::rgb(0.9, 0.9, 0.9), 1.5),
+                (Callout::PerfectClear, 64.0, Color::rgb(1.0, 0.85, 0.3), 3.0),
+            ];
+            for (kind, font_size, color, seconds) in callouts {
+                parent.spawn((
+                    TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font: ui_assets.font.clone(),
+                            font_size,
+                            color,
+                        },
+                    ),
+                    CalloutText {
+                        kind,
+                        timer: Timer::from_seconds(seconds, TimerMode::Once),
+                    },
+                ));
+            }
+        });
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..default()
+                },
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: ui_assets.font.clone(),
+                        font_size: CHAIN_FONT_SIZE,
+                        color: Color::rgb(0.9, 0.9, 0.9),
+                    },
+                ),
+                ChainText(Timer::from_seconds(0.3, TimerMode::Once)),
+            ));
+        });
+}
+
+fn update_hud(
+    settings: Res<GameSettings>,
+    stats: Res<GameStats>,
+    other_stats: Res<OtherGameStats>,
+    sprint: Option<Res<SprintProgress>>,
+    marathon: Option<Res<MarathonProgress>>,
+    cheese: Option<Res<CheeseProgress>>,
+    zen: Option<Res<ZenState>>,
+    client: Option<Res<ClientResource>>,
+    mut query: Query<&mut Text, With<HudText>>,
+) {
+    if !stats.is_changed() && !other_stats.is_changed() {
+        return;
+    }
+
+    let mut hud = if let Some(sprint) = &sprint {
+        let mut hud = format!(
+            "Time: {:.3}s\nLines: {}/{SPRINT_LINES}\nPieces: {}\nPPS: {:.2}",
+            stats.time.as_secs_f32(),
+            stats.lines,
+            stats.pieces,
+            stats.pieces_per_second()
+        );
+        for (i, split) in sprint.splits.iter().enumerate() {
+            let lines = (i + 1) * SPLIT_LINES;
+            hud += &format!("\n{lines} lines: {:.3}s", split.as_secs_f32());
+        }
+        if let Some(pb) = sprint.personal_best {
+            hud += &format!("\nBest: {:.3}s", pb.as_secs_f32());
+        }
+        hud
+    } else if let Some(marathon) = &marathon {
+        let goal = match settings.endless {
+            true => String::new(),
+            false => format!("/{MARATHON_LEVELS}"),
+        };
+        format!(
+            "Level: {}{goal}\nTime: {:.1}s\nLines: {}\nScore: {}\nTetris rate: {:.0}%",
+            marathon.level,
+            stats.time.as_secs_f32(),
+            stats.lines,
+            stats.score,
+            stats.tetris_rate() * 100.0
+        )
+    } else if let Some(cheese) = &cheese {
+        format!(
+            "Time: {:.3}s\nGarbage left: {}/{}\nPieces: {}",
+            stats.time.as_secs_f32(),
+            cheese.rows_left,
+            settings.cheese_rows,
+            stats.pieces
+        )
+    } else if let (GameMode::Puzzle, Some(puzzle)) = (settings.mode, &settings.puzzle) {
+        let goal = match puzzle.goal {
+            PuzzleGoal::Lines(lines) => format!("Clear {lines} lines"),
+            PuzzleGoal::PerfectClear => "Perfect clear".to_string(),
+            PuzzleGoal::TSpin => "T-spin".to_string(),
+        };
+        format!(
+            "Puzzle: {}\nGoal: {goal}\nPieces left: {}\nLines: {}",
+            puzzle.name,
+            puzzle.pieces.len().saturating_sub(stats.pieces),
+            stats.lines
+        )
+    } else if let Some(zen) = &zen {
+        format!(
+            "Lines: {}\nPieces: {}\nScore: {}\n\nG: gravity {}\nU: undo\nEsc: finish",
+            stats.lines,
+            stats.pieces,
+            stats.score,
+            if zen.gravity { "on" } else { "off" }
+        )
+    } else if settings.mode == GameMode::Ultra {
+        let left = ultra_time_left(&stats).as_secs_f32();
+        format!(
+            "Time left: {}:{:04.1}\nScore: {}\nLines: {}\nPieces: {}",
+            (left / 60.0) as u32,
+            left % 60.0,
+            stats.score,
+            stats.lines,
+            stats.pieces
+        )
+    } else {
+        format!(
+            "Time: {:.1}s\nLines: {}\nPieces: {}\nScore: {}",
+            stats.time.as_secs_f32(),
+            stats.lines,
+            stats.pieces,
+            stats.score
+        )
+    };
+    if client.is_some() {
+        hud += &match settings.mode {
+            GameMode::Sprint => format!("\n\nOpponent: {}/{SPRINT_LINES}", other_stats.lines),
+            GameMode::Ultra => format!("\n\nOpponent score: {}", other_stats.score),
+            GameMode::Coop => format!("\n\nPartner lines: {}", other_stats.lines),
+            _ => format!("\n\nOpponent lines: {}", other_stats.lines),
+        };
+    }
+
+    for mut text in &mut query {
+        text.sections[0].value = hud.clone();
+    }
+}
+
+fn clear_callout(e: &LinesClearedEvent) -> Option<String> {
+    let name = match (e.t_spin, e.lines) {
+        (TSpin::None, 4) => "Tetris".to_string(),
+        (TSpin::None, _) => return None,
+        (TSpin::Mini, 0) => "T-Spin Mini".to_string(),
+        (TSpin::Full, 0) => "T-Spin".to_string(),
+        (t_spin, lines) => {
+            let name = match t_spin {
+                TSpin::Mini => "T-Spin Mini",
+                _ => "T-Spin",
+            };
+            let lines = ["Single", "Double", "Triple"][lines.min(3) - 1];
+            format!("{name} {lines}")
+        }
+    };
+    Some(match e.back_to_back {
+        true => format!("Back-to-Back {name}"),
+        false => name,
+    })
+}
+
+fn update_callout(
+    time: Res<Time>,
+    mut cleared: EventReader<LinesClearedEvent>,
+    mut query: Query<(&mut Text, &mut CalloutText)>,
+) {
+    let cleared: Vec<_> = cleared.iter().copied().collect();
+    for (mut text, mut callout) in &mut query {
+        let shown = cleared.iter().filter_map(|e| match callout.kind {
+            Callout::Clear => clear_callout(e),
+            Callout::PerfectClear => e.perfect_clear.then(|| "Perfect Clear!".to_string()),
+        });
+        if let Some(shown) = shown.last() {
+            text.sections[0].value = shown;
+            callout.timer.reset();
+        }
+        callout.timer.tick(time.delta());
+        let alpha = callout.timer.percent_left();
+        text.sections[0].style.color.set_a(alpha);
+    }
+}
+
+fn update_chain_counter(
+    time: Res<Time>,
+    chain: Res<ClearChain>,
+    mut query: Query<(&mut Text, &mut ChainText)>,
+) {
+    for (mut text, mut counter) in &mut query {
+        if chain.is_changed() {
+            let mut counters = vec![];
+            if let Some(combo) = chain.combo.filter(|e| *e > 0) {
+                counters.push(format!("{combo} Combo"));
+            }
+            if let Some(chain) = chain.back_to_back.filter(|e| *e > 0) {
+                counters.push(format!("Back-to-Back x{chain}"));
+            }
+            text.sections[0].value = counters.join("\n");
+            counter.0.reset();
+        }
+        counter.0.tick(time.delta());
+        text.sections[0].style.font_size = CHAIN_FONT_SIZE * (1.0 + 0.4 * counter.0.percent_left());
+    }
+}
+
+fn setup_replay_ui(mut commands: Commands, ui_assets: Res<UiAssets>) {
+    let text_style = TextStyle {
+        font: ui_assets.font.clone(),
+        font_size: 20.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((TextBundle::from_section("", text_style.clone()), ReplayText));
+            parent.spawn(TextBundle::from_section(
+                "Space: pause    Up/Down: speed    Left/Right: seek    Esc: menu",
+                text_style,
+            ));
+        });
+}
+
+fn update_replay_ui(viewer: Res<ReplayViewer>, mut query: Query<&mut Text, With<ReplayText>>) {
+    if !viewer.is_changed() {
+        return;
+    }
+    for mut text in &mut query {
+        text.sections[0].value = format!(
+            "{:.1}s / {:.1}s    x{}{}",
+            viewer.time.as_secs_f32(),
+            viewer.duration().as_secs_f32(),
+            viewer.speed,
+            if viewer.paused { "    Paused" } else { "" }
+        );
+    }
+}
+
+/// Every state change despawns the ui so the chat is spawned again when missing
+fn spawn_chat_ui(
+    mut commands: Commands,
+    ui_assets: Res<UiAssets>,
+    query: Query<(), With<ChatRoot>>,
+) {
+    if !query.is_empty() {
+        return;
+    }
+
+    let text_style = TextStyle {
+        font: ui_assets.font.clone(),
+        font_size: 20.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left: Val::Px(10.0),
+                        bottom: Val::Px(10.0),
+                        ..default()
+                    },
+                    flex_direction: FlexDirection::ColumnReverse,
+                    ..default()
+                },
+                ..default()
+            },
+            ChatRoot,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                ChatText::History,
+            ));
+            parent.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                ChatText::Input,
+            ));
+        });
+}
+
+fn update_chat_ui(
+    input: Res<ChatInput>,
+    history: Res<ChatHistory>,
+    spawned: Query<(), Added<ChatText>>,
+    mut query: Query<(&mut Text, &ChatText)>,
+) {
+    if !input.is_changed() && !history.is_changed() && spawned.is_empty() {
+        return;
+    }
+
+    for (mut text, kind) in &mut query {
+        text.sections[0].value = match kind {
+            ChatText::History => history.iter().cloned().collect::<Vec<_>>().join("\n"),
+            ChatText::Input if input.open => format!("> {}_", input.text),
+            ChatText::Input => "Press enter to chat".to_string(),
+        };
+    }
+}
+
 fn button_system(
     mut commands: Commands,
     mut interaction_query: Query<
@@ -183,6 +1086,11 @@ fn button_system(
     >,
     mut host_ip: ResMut<HostAddress>,
     ip_input: Res<IpJoinInput>,
+    mut lobby_actions: EventWriter<LobbyAction>,
+    mut bot: Option<ResMut<BotPlayer>>,
+    mut handling: ResMut<Handling>,
+    mut settings: ResMut<GameSettings>,
+    client: Option<Res<ClientResource>>,
 ) {
     for (interaction, mut color, menu_button) in &mut interaction_query {
         match *interaction {
@@ -190,7 +1098,7 @@ fn button_system(
                 *color = PRESSED_BUTTON.into();
                 match menu_button {
                     MenuButton::Host => {
-                        commands.insert_resource(NextState(GameState::Playing));
+                        commands.insert_resource(NextState(GameState::Lobby));
                         commands.insert_resource(NextState(NetworkState::Host));
                     }
                     MenuButton::Join => {
@@ -198,9 +1106,74 @@ fn button_system(
                     }
                     MenuButton::JoinGo => {
                         **host_ip = ip_input.to_owned();
-                        commands.insert_resource(NextState(GameState::Playing));
+                        commands.insert_resource(NextState(GameState::Lobby));
                         commands.insert_resource(NextState(NetworkState::Client));
                     }
+                    MenuButton::Ready => lobby_actions.send(LobbyAction::ToggleReady),
+                    MenuButton::Mode => lobby_actions.send(LobbyAction::CycleMode),
+                    MenuButton::Gravity => lobby_actions.send(LobbyAction::CycleGravity),
+                    MenuButton::BestOf => lobby_actions.send(LobbyAction::CycleBestOf),
+                    MenuButton::StartLevel => lobby_actions.send(LobbyAction::CycleStartLevel),
+                    MenuButton::Endless => lobby_actions.send(LobbyAction::ToggleEndless),
+                    MenuButton::CheeseRows => lobby_actions.send(LobbyAction::CycleCheeseRows),
+                    MenuButton::Puzzle => lobby_actions.send(LobbyAction::CyclePuzzle),
+                    MenuButton::BoardSize => lobby_actions.send(LobbyAction::CycleBoardSize),
+                    MenuButton::PieceSet => lobby_actions.send(LobbyAction::CyclePieceSet),
+                    MenuButton::Rematch if client.is_none() => {
+                        settings.seed = rand::random();
+                        commands.insert_resource(NextState(GameState::Playing));
+                    }
+                    MenuButton::Rematch => {
+                        commands.insert_resource(NextState(GameState::Lobby));
+                    }
+                    MenuButton::Solo => {
+                        commands.insert_resource(NextState(GameState::Lobby));
+                    }
+                    MenuButton::Menu => {
+                        // Leaving the host state also stops listening for players
+                        commands.remove_resource::<ClientResource>();
+                        commands.remove_resource::<BotPlayer>();
+                        commands.insert_resource(NextState(NetworkState::None));
+                        commands.insert_resource(NextState(GameState::Menu));
+                    }
+                    MenuButton::Bot => {
+                        let (bot, connection) = BotPlayer::new();
+                        commands.insert_resource(bot);
+                        commands.insert_resource(connection);
+                        commands.insert_resource(NextState(GameState::Lobby));
+                    }
+                    MenuButton::BotDifficulty => {
+                        if let Some(bot) = bot.as_mut() {
+                            bot.difficulty = bot.difficulty.next();
+                        }
+                    }
+                    MenuButton::BotSpeed => {
+                        if let Some(bot) = bot.as_mut() {
+                            bot.speed = bot.speed.next();
+                        }
+                    }
+                    MenuButton::SoftDrop => {
+                        let i = SOFT_DROP_FACTORS
+                            .iter()
+                            .position(|f| *f == handling.soft_drop_factor);
+                        handling.soft_drop_factor =
+                            SOFT_DROP_FACTORS[i.map_or(0, |i| i + 1) % SOFT_DROP_FACTORS.len()];
+                    }
+                    MenuButton::Das => {
+                        let i = DAS_OPTIONS.iter().position(|d| *d == handling.das);
+                        handling.das = DAS_OPTIONS[i.map_or(0, |i| i + 1) % DAS_OPTIONS.len()];
+                    }
+                    MenuButton::Arr => {
+                        let i = ARR_OPTIONS.iter().position(|a| *a == handling.arr);
+                        handling.arr = ARR_OPTIONS[i.map_or(0, |i| i + 1) % ARR_OPTIONS.len()];
+                    }
+                    MenuButton::Replay => match Replay::load_latest() {
+                        Ok(replay) => {
+                            commands.insert_resource(ReplayViewer::new(replay));
+                            commands.insert_resource(NextState(GameState::Replay));
+                        }
+                        Err(e) => println!("{e}"),
+                    },
                 }
             }
             Interaction::Hovered => {
@@ -213,7 +1186,6 @@ fn button_system(
     }
 }
 
-const BACKSPACE_CHAR: char = 8 as char;
 fn ip_input_system(
     mut key_events: EventReader<ReceivedCharacter>,
     mut input: ResMut<IpJoinInput>,
@@ -2,6 +2,77 @@ use crate::tetris::*;
 use bevy::prelude::*;
 use core::ops::Deref;
 
+/// How each kind of tile looks
+#[derive(Resource)]
+pub struct Theme {
+    /// In the order of `THEMED_KINDS`
+    pub colors: [Color; 8],
+    /// Cycled through for the pieces of a piece set file
+    pub custom_colors: Vec<Color>,
+    /// Images in the assets folder tinted with the colors, in the order of
+    /// `THEMED_KINDS`
+    pub textures: [String; 8],
+    /// For the pieces of a piece set file
+    pub custom_texture: String,
+}
+
+/// The kinds with their own color, in the order of `Theme::colors`
+const THEMED_KINDS: [PieceKind; 8] = [
+    PieceKind::T,
+    PieceKind::S,
+    PieceKind::Z,
+    PieceKind::I,
+    PieceKind::J,
+    PieceKind::L,
+    PieceKind::O,
+    PieceKind::Garbage,
+];
+
+impl Default for Theme {
+    /// The guideline colors
+    fn default() -> Self {
+        Self {
+            colors: [
+                Color::hsl(285.0, 0.7, 0.7), // T purple
+                Color::hsl(120.0, 0.7, 0.7), // S green
+                Color::hsl(0.0, 0.7, 0.7),   // Z red
+                Color::hsl(185.0, 0.7, 0.7), // I cyan
+                Color::hsl(230.0, 0.7, 0.7), // J blue
+                Color::hsl(30.0, 0.7, 0.7),  // L orange
+                Color::hsl(55.0, 0.7, 0.7),  // O yellow
+                Color::hsl(0.0, 0.0, 0.5),   // Garbage gray
+            ],
+            // Spread around the color wheel, one for each pentomino
+            custom_colors: (0..18)
+                .map(|i| Color::hsl(i as f32 * 20.0, 0.7, 0.7))
+                .collect(),
+            textures: std::array::from_fn(|_| "tetris_tile.png".to_string()),
+            custom_texture: "tetris_tile.png".to_string(),
+        }
+    }
+}
+
+impl Theme {
+    pub fn color(&self, tile: &TetrisTile) -> Color {
+        match tile.kind {
+            PieceKind::Custom(i) => self.custom_colors[i as usize % self.custom_colors.len()],
+            kind => self.colors[themed_index(kind)],
+        }
+    }
+    pub fn texture(&self, tile: &TetrisTile) -> &str {
+        match tile.kind {
+            PieceKind::Custom(_) => &self.custom_texture,
+            kind => &self.textures[themed_index(kind)],
+        }
+    }
+}
+
+/// Anything else looks like garbage
+fn themed_index(kind: PieceKind) -> usize {
+    let i = THEMED_KINDS.iter().position(|e| *e == kind);
+    i.unwrap_or(THEMED_KINDS.len() - 1)
+}
+
 #[derive(Component, Clone)]
 pub struct OwnTile;
 
@@ -11,6 +82,7 @@ pub struct OtherTile;
 pub fn draw_falling(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
     own_board: Res<OwnTetrisBoard>,
     own_query: Query<Entity, With<FallingTile>>,
     own_piece: Res<CurrentPiece>,
@@ -19,13 +91,13 @@ pub fn draw_falling(
         for e in despawn {
             commands.entity(e).despawn();
         }
-        for (pos, tile) in piece.tiles.iter() {
+        for (pos, tile) in piece.tiles.iter().filter(|(e, _)| board.is_visible(*e)) {
             commands.spawn((
                 SpriteBundle {
-                    texture: asset_server.load("tetris_tile.png"),
+                    texture: asset_server.load(theme.texture(tile)),
                     transform: Transform::from_translation(board.get_position(*pos)),
                     sprite: Sprite {
-                        color: tile.color,
+                        color: theme.color(tile),
                         ..Default::default()
                     },
                     ..Default::default()
@@ -47,6 +119,7 @@ pub fn draw_falling(
 pub fn draw_tiles(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
     own_query: Query<Entity, With<OwnTile>>,
     own_board: Res<OwnTetrisBoard>,
     other_query: Query<Entity, With<OtherTile>>,
@@ -56,8 +129,9 @@ pub fn draw_tiles(
         own_query.iter().for_each(|e| commands.entity(e).despawn());
         spawn_tiles(
             own_board.deref(),
+            &theme,
             &mut commands,
-            asset_server.load("tetris_tile.png"),
+            &asset_server,
             OwnTile,
         );
     }
@@ -68,8 +142,9 @@ pub fn draw_tiles(
             .for_each(|e| commands.entity(e).despawn());
         spawn_tiles(
             other_board.deref(),
+            &theme,
             &mut commands,
-            asset_server.load("tetris_tile.png"),
+            &asset_server,
             OtherTile,
         );
     }
@@ -77,20 +152,21 @@ pub fn draw_tiles(
 
 fn spawn_tiles<T: Component + Clone>(
     board: &TetrisBoard,
+    theme: &Theme,
     commands: &mut Commands,
-    texture: Handle<Image>,
+    asset_server: &AssetServer,
     comp: T,
 ) {
     for (col, l) in board.tiles.iter().enumerate() {
-        for (row, t) in l.iter().enumerate() {
+        for (row, t) in l.iter().enumerate().skip(board.hidden_rows) {
             if let Some(tile) = t {
                 let board_position = [col as i32, row as i32].into();
                 commands.spawn((
                     SpriteBundle {
-                        texture: texture.clone(),
+                        texture: asset_server.load(theme.texture(tile)),
                         transform: Transform::from_translation(board.get_position(board_position)),
                         sprite: Sprite {
-                            color: tile.color,
+                            color: theme.color(tile),
                             ..Default::default()
                         },
                         ..Default::default()
@@ -101,3 +177,37 @@ fn spawn_tiles<T: Component + Clone>(
         }
     }
 }
+
+#[derive(Component)]
+pub struct OtherFallingTile;
+
+pub fn draw_other_falling(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
+    other_board: Res<OtherTetrisBoard>,
+    other_piece: Res<OtherCurrentPiece>,
+    query: Query<Entity, With<OtherFallingTile>>,
+) {
+    if !other_piece.is_changed() {
+        return;
+    }
+    query.iter().for_each(|e| commands.entity(e).despawn());
+    for (pos, tile) in other_piece
+        .iter()
+        .filter(|(e, _)| other_board.is_visible(*e))
+    {
+        commands.spawn((
+            SpriteBundle {
+                texture: asset_server.load(theme.texture(tile)),
+                transform: Transform::from_translation(other_board.get_position(*pos)),
+                sprite: Sprite {
+                    color: theme.color(tile),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            OtherFallingTile,
+        ));
+    }
+}